@@ -0,0 +1,391 @@
+//! Tap, long-press, double-tap and chord actions of keys, so that one key
+//! can drive several HID buttons.
+
+/// Timings of the key actions, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyTimings {
+    /// Hold time after which a press is a long-press.
+    pub long_press: f32,
+    /// Longest time from the release of a tap to the second press of a
+    /// double-tap.
+    pub double_tap: f32,
+    /// Longest time between the presses of the two keys of a chord.
+    pub chord: f32,
+}
+
+impl Default for KeyTimings {
+    fn default() -> Self {
+        Self {
+            long_press: 0.5,
+            double_tap: 0.25,
+            chord: 0.05,
+        }
+    }
+}
+
+/// HID buttons a key presses, by bit index, for each way of pressing it.
+///
+/// A tap only counts once the double-tap time has passed if the key has a
+/// double-tap button, so keys that should react at once are best left
+/// without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    /// The key, by row and column.
+    pub key: (usize, usize),
+    pub tap: Option<u8>,
+    pub long_press: Option<u8>,
+    pub double_tap: Option<u8>,
+}
+
+/// HID button pressed by pressing two keys together. Keys of a chord
+/// without a binding of their own do nothing alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChordBinding {
+    /// The keys, by row and column.
+    pub keys: [(usize, usize); 2],
+    pub button: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    /// Held for the given time. Times are kept since the event rather than
+    /// as absolute time, which would stop advancing in `f32` after a day or
+    /// two of uptime.
+    Pressed(f32),
+    /// Released after a tap the given time ago, waiting for a second press.
+    Released(f32),
+    /// Pressed again within the double-tap time.
+    SecondPress,
+    /// Already acted on, waiting for the release.
+    Done,
+}
+
+/// Recognises the actions of the bound keys from their press and release
+/// events.
+#[derive(Debug, Clone)]
+pub struct KeyActions {
+    timings: KeyTimings,
+    keys: Vec<(KeyBinding, Phase)>,
+    chords: Vec<ChordBinding>,
+    buttons: Vec<u8>,
+}
+
+impl KeyActions {
+    pub fn new(timings: KeyTimings, bindings: &[KeyBinding], chords: &[ChordBinding]) -> Self {
+        let mut keys: Vec<(KeyBinding, Phase)> = bindings
+            .iter()
+            .map(|binding| (*binding, Phase::Idle))
+            .collect();
+        for key in chords.iter().flat_map(|chord| chord.keys) {
+            if !keys.iter().any(|(binding, _)| binding.key == key) {
+                let binding = KeyBinding {
+                    key,
+                    tap: None,
+                    long_press: None,
+                    double_tap: None,
+                };
+                keys.push((binding, Phase::Idle));
+            }
+        }
+        Self {
+            timings,
+            keys,
+            chords: chords.to_vec(),
+            buttons: Vec::new(),
+        }
+    }
+
+    pub fn timings(&self) -> KeyTimings {
+        self.timings
+    }
+
+    pub fn set_timings(&mut self, timings: KeyTimings) {
+        self.timings = timings;
+    }
+
+    /// Whether the key has any action, in which case it should not press
+    /// a button of its own as well.
+    pub fn is_bound(&self, key: (usize, usize)) -> bool {
+        self.keys.iter().any(|(binding, _)| binding.key == key)
+    }
+
+    /// Takes a press or release of a key.
+    pub fn key(&mut self, key: (usize, usize), pressed: bool) {
+        let Some(index) = self.keys.iter().position(|(binding, _)| binding.key == key) else {
+            return;
+        };
+        if pressed && self.chord(key) {
+            return;
+        }
+
+        let timings = self.timings;
+        let (binding, phase) = &mut self.keys[index];
+        *phase = match (*phase, pressed) {
+            (Phase::Released(since), true) if since <= timings.double_tap => Phase::SecondPress,
+            (Phase::Released(_), true) => {
+                // The update that would have ended the tap came too late
+                self.buttons.extend(binding.tap);
+                Phase::Pressed(0.0)
+            }
+            (_, true) => Phase::Pressed(0.0),
+            (Phase::Pressed(_), false) if binding.double_tap.is_some() => Phase::Released(0.0),
+            (Phase::Pressed(_), false) => {
+                self.buttons.extend(binding.tap);
+                Phase::Idle
+            }
+            (Phase::SecondPress, false) => {
+                self.buttons.extend(binding.double_tap);
+                Phase::Idle
+            }
+            (_, false) => Phase::Idle,
+        };
+    }
+
+    /// Completes a chord with a key that was just pressed, if the other key
+    /// of one was pressed shortly before.
+    fn chord(&mut self, key: (usize, usize)) -> bool {
+        let held = |keys: &[(KeyBinding, Phase)], other| {
+            keys.iter().any(|(binding, phase)| match phase {
+                Phase::Pressed(held) => binding.key == other && *held <= self.timings.chord,
+                _ => false,
+            })
+        };
+        let Some(chord) = self.chords.iter().copied().find(|chord| match chord.keys {
+            [a, b] if a == key => held(&self.keys, b),
+            [a, b] if b == key => held(&self.keys, a),
+            _ => false,
+        }) else {
+            return false;
+        };
+
+        self.buttons.push(chord.button);
+        for (binding, phase) in self.keys.iter_mut() {
+            if chord.keys.contains(&binding.key) {
+                *phase = Phase::Done;
+            }
+        }
+        true
+    }
+
+    /// Advances the time by `dt` seconds, ending the long-presses and taps
+    /// that have run out of time.
+    pub fn update(&mut self, dt: f32) {
+        for (binding, phase) in self.keys.iter_mut() {
+            if let Phase::Pressed(time) | Phase::Released(time) = phase {
+                *time += dt;
+            }
+            match *phase {
+                Phase::Pressed(held)
+                    if binding.long_press.is_some() && held >= self.timings.long_press =>
+                {
+                    self.buttons.extend(binding.long_press);
+                    *phase = Phase::Done;
+                }
+                Phase::Released(since) if since > self.timings.double_tap => {
+                    self.buttons.extend(binding.tap);
+                    *phase = Phase::Idle;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Takes the buttons of the actions recognised since the last call, by
+    /// bit index, oldest first.
+    pub fn take_buttons(&mut self) -> impl Iterator<Item = u8> + '_ {
+        self.buttons.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAP_ONLY: (usize, usize) = (0, 0);
+    const ALL: (usize, usize) = (0, 1);
+    const CHORD_ONLY: (usize, usize) = (1, 0);
+
+    fn actions() -> KeyActions {
+        let bindings = [
+            KeyBinding {
+                key: TAP_ONLY,
+                tap: Some(1),
+                long_press: None,
+                double_tap: None,
+            },
+            KeyBinding {
+                key: ALL,
+                tap: Some(2),
+                long_press: Some(3),
+                double_tap: Some(4),
+            },
+        ];
+        let chords = [
+            ChordBinding {
+                keys: [TAP_ONLY, CHORD_ONLY],
+                button: 5,
+            },
+            ChordBinding {
+                keys: [ALL, CHORD_ONLY],
+                button: 6,
+            },
+        ];
+        KeyActions::new(KeyTimings::default(), &bindings, &chords)
+    }
+
+    fn take(actions: &mut KeyActions) -> Vec<u8> {
+        actions.take_buttons().collect()
+    }
+
+    fn press(actions: &mut KeyActions, key: (usize, usize), time: f32) {
+        actions.key(key, true);
+        actions.update(time);
+        actions.key(key, false);
+    }
+
+    #[test]
+    fn binds_chord_keys_too() {
+        let actions = actions();
+        assert!(actions.is_bound(TAP_ONLY));
+        assert!(actions.is_bound(CHORD_ONLY));
+        assert!(!actions.is_bound((2, 2)));
+    }
+
+    #[test]
+    fn taps_on_release_without_double_tap() {
+        let mut actions = actions();
+        actions.key(TAP_ONLY, true);
+        actions.update(0.1);
+        assert!(take(&mut actions).is_empty());
+        actions.key(TAP_ONLY, false);
+        assert_eq!(take(&mut actions), [1]);
+        // Held past the long-press time, but it has none
+        press(&mut actions, TAP_ONLY, 1.0);
+        assert_eq!(take(&mut actions), [1]);
+    }
+
+    #[test]
+    fn tap_waits_for_the_double_tap_time() {
+        let mut actions = actions();
+        press(&mut actions, ALL, 0.1);
+        actions.update(0.2);
+        assert!(take(&mut actions).is_empty());
+        actions.update(0.1);
+        assert_eq!(take(&mut actions), [2]);
+    }
+
+    #[test]
+    fn tap_completes_on_a_late_update() {
+        let mut actions = actions();
+        press(&mut actions, ALL, 0.1);
+        // No update for a long while, then one that covers it all
+        actions.update(2.0);
+        assert_eq!(take(&mut actions), [2]);
+
+        // The window shrank under a pending tap, so the next press ends it
+        press(&mut actions, ALL, 0.1);
+        actions.update(0.2);
+        actions.set_timings(KeyTimings {
+            double_tap: 0.1,
+            ..actions.timings()
+        });
+        actions.key(ALL, true);
+        assert_eq!(take(&mut actions), [2]);
+        actions.key(ALL, false);
+        actions.update(0.2);
+        assert_eq!(take(&mut actions), [2]);
+    }
+
+    #[test]
+    fn double_tap_replaces_the_taps() {
+        let mut actions = actions();
+        press(&mut actions, ALL, 0.1);
+        actions.update(0.1);
+        press(&mut actions, ALL, 0.1);
+        assert_eq!(take(&mut actions), [4]);
+        actions.update(1.0);
+        assert!(take(&mut actions).is_empty());
+    }
+
+    #[test]
+    fn long_press_fires_while_held() {
+        let mut actions = actions();
+        actions.key(ALL, true);
+        actions.update(0.4);
+        assert!(take(&mut actions).is_empty());
+        actions.update(0.1);
+        assert_eq!(take(&mut actions), [3]);
+        // Nothing more on the release
+        actions.update(1.0);
+        actions.key(ALL, false);
+        actions.update(1.0);
+        assert!(take(&mut actions).is_empty());
+    }
+
+    #[test]
+    fn chord_replaces_the_actions_of_its_keys() {
+        let mut actions = actions();
+        actions.key(TAP_ONLY, true);
+        actions.update(0.02);
+        actions.key(CHORD_ONLY, true);
+        assert_eq!(take(&mut actions), [5]);
+        // Done until released, so no tap or long-press of the bound key
+        actions.update(1.0);
+        actions.key(CHORD_ONLY, false);
+        actions.key(TAP_ONLY, false);
+        actions.update(1.0);
+        assert!(take(&mut actions).is_empty());
+
+        // Either key can come first
+        actions.key(CHORD_ONLY, true);
+        actions.key(ALL, true);
+        actions.update(1.0);
+        actions.key(ALL, false);
+        actions.key(CHORD_ONLY, false);
+        actions.update(1.0);
+        assert_eq!(take(&mut actions), [6]);
+    }
+
+    #[test]
+    fn keys_too_far_apart_are_no_chord() {
+        let mut actions = actions();
+        actions.key(TAP_ONLY, true);
+        actions.update(0.1);
+        actions.key(CHORD_ONLY, true);
+        actions.key(CHORD_ONLY, false);
+        actions.key(TAP_ONLY, false);
+        // The bound key taps, the chord-only key does nothing alone
+        assert_eq!(take(&mut actions), [1]);
+    }
+
+    #[test]
+    fn buttons_come_oldest_first() {
+        let mut actions = actions();
+        actions.key(ALL, true);
+        actions.update(0.5);
+        press(&mut actions, TAP_ONLY, 0.1);
+        actions.key(ALL, false);
+        assert_eq!(take(&mut actions), [3, 1]);
+    }
+
+    #[test]
+    fn keeps_timing_after_days_of_uptime() {
+        let mut actions = actions();
+        // One long step stands in for days of updates
+        actions.update(200_000.0);
+        actions.key(ALL, true);
+        for _ in 0..60 {
+            actions.update(0.01);
+        }
+        assert_eq!(take(&mut actions), [3]);
+        actions.key(ALL, false);
+
+        actions.key(ALL, true);
+        actions.key(ALL, false);
+        for _ in 0..30 {
+            actions.update(0.01);
+        }
+        assert_eq!(take(&mut actions), [2]);
+    }
+}
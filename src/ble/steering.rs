@@ -1,15 +1,19 @@
 #![allow(dead_code)]
 
+pub use esp32_ble_steering_rs::command::{Axis, Command};
+use esp32_ble_steering_rs::health::Diagnostics;
 use esp32_nimble::{
-    enums::*, hid::*, utilities::mutex::Mutex, BLEAdvertisementData, BLECharacteristic, BLEDevice,
-    BLEHIDDevice, BLEServer,
+    enums::*, hid::*, utilities::mutex::Mutex, uuid128, BLEAdvertisementData, BLECharacteristic,
+    BLEDevice, BLEHIDDevice, BLEServer, NimbleProperties,
 };
 // use log::info;
+use std::collections::VecDeque;
 use std::sync::Arc;
 use zerocopy::IntoBytes;
 use zerocopy_derive::{Immutable, IntoBytes};
 
 const STEERING_ID: u8 = 0x03;
+const CONTROL_ID: u8 = 0x04;
 
 const HID_REPORT_DESCRIPTOR: &[u8] = hid!(
     (USAGE_PAGE, 0x01),       // Generic Desktop
@@ -22,8 +26,8 @@ const HID_REPORT_DESCRIPTOR: &[u8] = hid!(
     (LOGICAL_MAXIMUM, 0x01), // 1
     (REPORT_SIZE, 1),
     (USAGE_MINIMUM, 0x01), // Button 1
-    (USAGE_MAXIMUM, 32),   // Button 32
-    (REPORT_COUNT, 32),    // 32 buttons
+    (USAGE_MAXIMUM, 64),   // Button 64
+    (REPORT_COUNT, 64),    // 64 buttons
     (HIDINPUT, 0x02),      // INPUT (Data,Var,Abs)
     // ------------------------------------ Steerings
     (USAGE_PAGE, 0x02),            // Simulation Controls
@@ -48,13 +52,24 @@ const HID_REPORT_DESCRIPTOR: &[u8] = hid!(
     (USAGE, 0x31),      // Y
     (HIDINPUT, 0x02),   // INPUT (Data,Var,Abs)
     (END_COLLECTION),   // Physical(End)
+    // ------------------------------------ Control
+    (REPORT_ID, CONTROL_ID),       // Report ID 4
+    (USAGE_PAGE, 0x00, 0xFF),      // Vendor Defined
+    (USAGE, 0x01),                 // Command
+    (USAGE, 0x02),                 // Argument
+    (LOGICAL_MINIMUM, 0x00),       // 0
+    (LOGICAL_MAXIMUM, 0xFF, 0x00), // 255
+    (REPORT_SIZE, 8),
+    (REPORT_COUNT, 12), // Command, arguments (up to 11 bytes), see `command::REPORT_LEN`
+    (FEATURE, 0x02),    // FEATURE (Data,Var,Abs)
     // ------------------------------------ Application(End)
     (END_COLLECTION)
 );
+
 #[derive(IntoBytes, Immutable, Debug)]
 #[repr(packed)]
 struct SteeringReport {
-    buttons: u32,
+    buttons: u64,
     steering: i16,
     accelerator: i16,
     brake: i16,
@@ -65,7 +80,9 @@ struct SteeringReport {
 pub struct Steering {
     server: &'static mut BLEServer,
     input_steering: Arc<Mutex<BLECharacteristic>>,
+    diagnostics: Arc<Mutex<BLECharacteristic>>,
     steering_report: Arc<Mutex<SteeringReport>>,
+    commands: Arc<Mutex<VecDeque<Command>>>,
 }
 
 impl Steering {
@@ -81,6 +98,7 @@ impl Steering {
         let mut hid = BLEHIDDevice::new(server);
 
         let input_steering = hid.input_report(STEERING_ID);
+        let control = hid.feature_report(CONTROL_ID);
 
         hid.manufacturer("Baohuiming.net");
         hid.pnp(0x02, 0x2838, 0x0100, 0x0525);
@@ -90,6 +108,15 @@ impl Steering {
 
         hid.set_battery_level(100);
 
+        // Vendor service with the sensor health, see `health::Diagnostics` for the layout
+        let diagnostics = server
+            .create_service(uuid128!("5d3e0001-8b1f-4c3a-9f61-2a7c2e6b9d10"))
+            .lock()
+            .create_characteristic(
+                uuid128!("5d3e0002-8b1f-4c3a-9f61-2a7c2e6b9d10"),
+                NimbleProperties::READ,
+            );
+
         let ble_advertising = device.get_advertising();
         ble_advertising.lock().scan_response(false).set_data(
             BLEAdvertisementData::new()
@@ -108,10 +135,20 @@ impl Steering {
             steering: 0,
         }));
 
+        let commands = Arc::new(Mutex::new(VecDeque::new()));
+        let pending = commands.clone();
+        control.lock().on_write(move |args| {
+            if let Some(command) = Command::parse(args.recv_data()) {
+                pending.lock().push_back(command);
+            }
+        });
+
         Ok(Self {
             server,
             input_steering,
+            diagnostics,
             steering_report,
+            commands,
         })
     }
 
@@ -136,11 +173,21 @@ impl Steering {
         report.y = y_value;
     }
 
-    pub fn set_buttons(&self, buttons: u32) {
+    pub fn set_buttons(&self, buttons: u64) {
         let mut report = self.steering_report.lock();
         report.buttons = buttons
     }
 
+    /// Sets the value the host reads from the diagnostics characteristic.
+    pub fn set_diagnostics(&self, diagnostics: &Diagnostics) {
+        self.diagnostics.lock().set_value(diagnostics.as_bytes());
+    }
+
+    /// Takes the oldest command received from the host, if any.
+    pub fn take_command(&self) -> Option<Command> {
+        self.commands.lock().pop_front()
+    }
+
     pub fn send_report(&self) {
         // SteeringReport { buttons: 1, x: 2047, y: 2047, accelerator: 0, brake: 0, steering: 0 }
         // [1, 0, 255, 7, 255, 7, 0, 0, 0, 0, 0, 0]
@@ -0,0 +1,369 @@
+//! Commands the host sends through the control feature report.
+
+use crate::actions::KeyTimings;
+use crate::controls::{Curve, CURVE_POINTS};
+use crate::filter::StageConfig;
+use crate::gesture::{Gesture, GestureAction};
+
+/// Length of the control report: the command and up to 11 bytes of
+/// arguments, so every command has to fit in that.
+pub const REPORT_LEN: usize = 12;
+
+/// Steps of a fraction from 0.0 to 1.0 sent as a u16.
+const FRACTION_STEPS: f32 = 10000.0;
+
+/// Axes of the steering report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    Steering,
+    Accelerator,
+    Brake,
+    X,
+    Y,
+}
+
+impl Axis {
+    pub const COUNT: usize = 5;
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Axis::Steering),
+            1 => Some(Axis::Accelerator),
+            2 => Some(Axis::Brake),
+            3 => Some(Axis::X),
+            4 => Some(Axis::Y),
+            _ => None,
+        }
+    }
+}
+
+/// Commands the host can send through the control feature report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Capture the current steering angle as straight ahead.
+    Recenter,
+    /// Switch to the profile with the given index.
+    SelectProfile(u8),
+    /// Select the steering source used from the next boot.
+    SelectSource(u8),
+    /// Invert the encoder direction from the next boot.
+    InvertSource(bool),
+    /// Set the IMU sample rate in Hz from the next boot.
+    SetSampleRate(u16),
+    /// Set the IMU low-pass filter from the next boot.
+    SetDlpf(u8),
+    /// Set or, with `None`, remove a filter stage of an axis.
+    SetFilter {
+        axis: Axis,
+        stage: u8,
+        config: Option<StageConfig>,
+    },
+    /// Mount the IMU in one of the axis-aligned orientations.
+    SetMount(u8),
+    /// Take the next capture of the mount calibration.
+    CalibrateMount,
+    /// Set what a gesture does.
+    SetGestureAction {
+        gesture: Gesture,
+        action: GestureAction,
+    },
+    /// Set the threshold of a gesture, see `GestureConfig` for the units.
+    SetGestureThreshold { gesture: Gesture, threshold: f32 },
+    /// Set what steers while the steering sensor is out, see `FallbackKind`.
+    SetFallback(u8),
+    /// Set the timings of the key actions.
+    SetKeyTimings(KeyTimings),
+    /// Set how the shift key switches keypad banks, see `LayerMode`.
+    SetLayerMode(u8),
+    /// Take the next capture of the joystick calibration.
+    CalibrateJoystick,
+    /// Set how the joystick deadzone is shaped, see `DeadzoneMode`.
+    SetDeadzoneMode(u8),
+    /// Set the deadzones of a joystick axis, see `AxisResponse`.
+    SetAxisDeadzones {
+        axis: Axis,
+        deadzone: f32,
+        outer: f32,
+        anti_deadzone: f32,
+    },
+    /// Set the response curve of a joystick axis.
+    SetAxisCurve { axis: Axis, curve: Curve },
+}
+
+impl Command {
+    /// Parses the data of the control report, the command first.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        match data.first()? {
+            0x01 => Some(Command::Recenter),
+            0x02 => Some(Command::SelectProfile(*data.get(1)?)),
+            0x03 => Some(Command::SelectSource(*data.get(1)?)),
+            0x04 => Some(Command::InvertSource(*data.get(1)? != 0)),
+            0x05 => Some(Command::SetSampleRate(u16::from_le_bytes([
+                *data.get(1)?,
+                *data.get(2)?,
+            ]))),
+            0x06 => Some(Command::SetDlpf(*data.get(1)?)),
+            // axis, stage, kind, two f32 parameters
+            0x07 => {
+                let param =
+                    |i: usize| Some(f32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?));
+                let config = match data.get(3)? {
+                    0 => None,
+                    1 => Some(StageConfig::OneEuro {
+                        min_cutoff: param(4)?,
+                        beta: param(8)?,
+                    }),
+                    2 => Some(StageConfig::Ema { cutoff: param(4)? }),
+                    3 => Some(StageConfig::Slew {
+                        max_rate: param(4)?,
+                    }),
+                    _ => return None,
+                };
+                if config.is_some_and(|config| !config.valid()) {
+                    return None;
+                }
+                Some(Command::SetFilter {
+                    axis: Axis::from_u8(*data.get(1)?)?,
+                    stage: *data.get(2)?,
+                    config,
+                })
+            }
+            0x08 => Some(Command::SetMount(*data.get(1)?)),
+            0x09 => Some(Command::CalibrateMount),
+            // gesture, action kind, button index
+            0x0A => Some(Command::SetGestureAction {
+                gesture: Gesture::from_u8(*data.get(1)?)?,
+                action: match data.get(2)? {
+                    0 => GestureAction::None,
+                    1 => GestureAction::Button(*data.get(3)?),
+                    2 => GestureAction::Recenter,
+                    3 => GestureAction::NextProfile,
+                    _ => return None,
+                },
+            }),
+            // gesture, f32 threshold
+            0x0B => Some(Command::SetGestureThreshold {
+                gesture: Gesture::from_u8(*data.get(1)?)?,
+                threshold: f32::from_le_bytes(data.get(2..6)?.try_into().ok()?),
+            }),
+            0x0C => Some(Command::SetFallback(*data.get(1)?)),
+            // long-press, double-tap and chord times, u16 milliseconds
+            0x0D => {
+                let millis = |i: usize| {
+                    let bytes = data.get(i..i + 2)?.try_into().ok()?;
+                    Some(u16::from_le_bytes(bytes) as f32 / 1000.0)
+                };
+                Some(Command::SetKeyTimings(KeyTimings {
+                    long_press: millis(1)?,
+                    double_tap: millis(3)?,
+                    chord: millis(5)?,
+                }))
+            }
+            0x0E => Some(Command::SetLayerMode(*data.get(1)?)),
+            0x0F => Some(Command::CalibrateJoystick),
+            0x10 => Some(Command::SetDeadzoneMode(*data.get(1)?)),
+            // axis, deadzone, outer deadzone and anti-deadzone as fractions
+            0x11 => {
+                let fraction = |i: usize| fraction(data.get(i..i + 2)?);
+                Some(Command::SetAxisDeadzones {
+                    axis: Axis::from_u8(*data.get(1)?)?,
+                    deadzone: fraction(2)?,
+                    outer: fraction(4)?,
+                    anti_deadzone: fraction(6)?,
+                })
+            }
+            // axis, curve kind, f32 parameter
+            0x12 => {
+                let param = || Some(f32::from_le_bytes(data.get(3..7)?.try_into().ok()?));
+                Some(Command::SetAxisCurve {
+                    axis: Axis::from_u8(*data.get(1)?)?,
+                    curve: match data.get(2)? {
+                        0 => Curve::Linear,
+                        1 => Curve::Exponential(param()?),
+                        2 => Curve::SCurve(param()?),
+                        _ => return None,
+                    },
+                })
+            }
+            // axis, points of a custom curve as fractions
+            0x13 => {
+                let mut points = [0.0; CURVE_POINTS];
+                for (i, point) in points.iter_mut().enumerate() {
+                    *point = fraction(data.get(2 + 2 * i..4 + 2 * i)?)?;
+                }
+                Some(Command::SetAxisCurve {
+                    axis: Axis::from_u8(*data.get(1)?)?,
+                    curve: Curve::Points(points),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A fraction from 0.0 to 1.0, sent as u16 ten-thousandths.
+fn fraction(bytes: &[u8]) -> Option<f32> {
+    let value = u16::from_le_bytes(bytes.try_into().ok()?) as f32 / FRACTION_STEPS;
+    (value <= 1.0).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A control report as the host sends it, padded to full length.
+    fn report(data: &[u8]) -> [u8; REPORT_LEN] {
+        assert!(data.len() <= REPORT_LEN, "{} bytes", data.len());
+        let mut report = [0; REPORT_LEN];
+        report[..data.len()].copy_from_slice(data);
+        report
+    }
+
+    fn fraction_bytes(value: f32) -> [u8; 2] {
+        ((value * FRACTION_STEPS).round() as u16).to_le_bytes()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn parses_short_commands() {
+        assert_eq!(Command::parse(&report(&[0x01])), Some(Command::Recenter));
+        assert_eq!(
+            Command::parse(&report(&[0x05, 0xF4, 0x01])),
+            Some(Command::SetSampleRate(500))
+        );
+        assert_eq!(
+            Command::parse(&report(&[0x0A, 1, 1, 7])),
+            Some(Command::SetGestureAction {
+                gesture: Gesture::Shake,
+                action: GestureAction::Button(7),
+            })
+        );
+        assert_eq!(Command::parse(&report(&[0x00])), None);
+        assert_eq!(Command::parse(&[]), None);
+    }
+
+    #[test]
+    fn parses_filter_stage() {
+        let mut data = vec![0x07, 3, 1, 1];
+        data.extend(1.5f32.to_le_bytes());
+        data.extend(0.01f32.to_le_bytes());
+        assert_eq!(
+            Command::parse(&report(&data)),
+            Some(Command::SetFilter {
+                axis: Axis::X,
+                stage: 1,
+                config: Some(StageConfig::OneEuro {
+                    min_cutoff: 1.5,
+                    beta: 0.01,
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_filter_stage_that_cannot_work() {
+        let stage = |kind: u8, a: f32, b: f32| {
+            let mut data = vec![0x07, 0, 0, kind];
+            data.extend(a.to_le_bytes());
+            data.extend(b.to_le_bytes());
+            Command::parse(&report(&data))
+        };
+        for bad in [f32::NAN, f32::INFINITY, -1.0, 0.0] {
+            assert_eq!(stage(1, bad, 0.1), None, "{bad}");
+            assert_eq!(stage(2, bad, 0.0), None, "{bad}");
+            assert_eq!(stage(3, bad, 0.0), None, "{bad}");
+        }
+        for bad in [f32::NAN, f32::NEG_INFINITY, -0.1] {
+            assert_eq!(stage(1, 1.0, bad), None, "{bad}");
+        }
+        // No speed coefficient is a plain low-pass, which is fine
+        assert!(stage(1, 1.0, 0.0).is_some());
+        assert!(stage(3, 360.0, 0.0).is_some());
+    }
+
+    #[test]
+    fn parses_key_timings_in_milliseconds() {
+        let mut data = vec![0x0D];
+        for millis in [600u16, 300, 40] {
+            data.extend(millis.to_le_bytes());
+        }
+        let Some(Command::SetKeyTimings(timings)) = Command::parse(&report(&data)) else {
+            panic!("not parsed");
+        };
+        assert!(close(timings.long_press, 0.6));
+        assert!(close(timings.double_tap, 0.3));
+        assert!(close(timings.chord, 0.04));
+    }
+
+    #[test]
+    fn parses_axis_deadzones() {
+        let mut data = vec![0x11, 4];
+        for value in [0.08, 0.02, 0.15] {
+            data.extend(fraction_bytes(value));
+        }
+        let Some(Command::SetAxisDeadzones {
+            axis,
+            deadzone,
+            outer,
+            anti_deadzone,
+        }) = Command::parse(&report(&data))
+        else {
+            panic!("not parsed");
+        };
+        assert_eq!(axis, Axis::Y);
+        assert!(close(deadzone, 0.08) && close(outer, 0.02) && close(anti_deadzone, 0.15));
+
+        // Past full deflection
+        data[2..4].copy_from_slice(&10001u16.to_le_bytes());
+        assert_eq!(Command::parse(&report(&data)), None);
+    }
+
+    #[test]
+    fn parses_axis_curve() {
+        let mut data = vec![0x12, 3, 1];
+        data.extend(2.5f32.to_le_bytes());
+        assert_eq!(
+            Command::parse(&report(&data)),
+            Some(Command::SetAxisCurve {
+                axis: Axis::X,
+                curve: Curve::Exponential(2.5),
+            })
+        );
+        assert_eq!(
+            Command::parse(&report(&[0x12, 3, 0])),
+            Some(Command::SetAxisCurve {
+                axis: Axis::X,
+                curve: Curve::Linear,
+            })
+        );
+        assert_eq!(Command::parse(&report(&[0x12, 3, 9])), None);
+    }
+
+    #[test]
+    fn curve_points_round_trip_through_a_full_report() {
+        let points = [0.0, 0.1234, 0.3, 0.6543, 1.0];
+        let mut data = vec![0x13, 3];
+        for point in points {
+            data.extend(fraction_bytes(point));
+        }
+        // The largest command, filling the report
+        assert_eq!(data.len(), REPORT_LEN);
+        let Some(Command::SetAxisCurve {
+            axis,
+            curve: Curve::Points(parsed),
+        }) = Command::parse(&report(&data))
+        else {
+            panic!("not parsed");
+        };
+        assert_eq!(axis, Axis::X);
+        for (a, b) in points.iter().zip(parsed) {
+            assert!(close(*a, b), "{points:?} {parsed:?}");
+        }
+
+        // Cut short, it is not taken with a missing point
+        assert_eq!(Command::parse(&data[..REPORT_LEN - 1]), None);
+    }
+}
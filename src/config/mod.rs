@@ -0,0 +1,5 @@
+mod store;
+pub use store::*;
+
+mod profile;
+pub use profile::*;
@@ -0,0 +1,167 @@
+use esp32_ble_steering_rs::filter::StageConfig;
+use esp32_ble_steering_rs::fusion::AxisMode;
+
+/// What the steering axis reports while its sensor is degraded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hold {
+    /// Keep the last good value.
+    Last,
+    /// Return to straight ahead.
+    Centre,
+}
+
+/// Steering settings that differ between games.
+#[derive(Debug, Clone, Copy)]
+pub struct Profile {
+    pub name: &'static str,
+    /// Rotation from full left to full right, in degrees.
+    pub lock_to_lock: f32,
+    /// Width of the progressive zone before each lock, in degrees.
+    pub end_zone: f32,
+    /// How much the end zone flattens the response, from 0.0 (linear)
+    /// to 1.0 (no further output change right at the lock).
+    pub end_zone_strength: f32,
+    /// Rotation of the IMU that steers. Encoders ignore it.
+    pub mode: AxisMode,
+    /// IMU pitch in degrees that gives full throttle, negative to tilt the
+    /// other way, or `None` to leave the throttle to the pedal.
+    pub throttle_pitch: Option<f32>,
+    /// Steering output while the sensor is degraded.
+    pub hold: Hold,
+    /// Default filter stages for the steering angle, in degrees.
+    pub smoothing: &'static [StageConfig],
+}
+
+pub const PROFILES: [Profile; 7] = [
+    Profile {
+        name: "kart",
+        lock_to_lock: 270.0,
+        end_zone: 20.0,
+        end_zone_strength: 0.5,
+        mode: AxisMode::Roll,
+        throttle_pitch: None,
+        hold: Hold::Centre,
+        smoothing: &[StageConfig::OneEuro {
+            min_cutoff: 1.5,
+            beta: 0.15,
+        }],
+    },
+    Profile {
+        name: "rally",
+        lock_to_lock: 540.0,
+        end_zone: 40.0,
+        end_zone_strength: 0.6,
+        mode: AxisMode::Roll,
+        throttle_pitch: None,
+        hold: Hold::Centre,
+        smoothing: &[StageConfig::OneEuro {
+            min_cutoff: 1.0,
+            beta: 0.1,
+        }],
+    },
+    Profile {
+        name: "road",
+        lock_to_lock: 900.0,
+        end_zone: 60.0,
+        end_zone_strength: 0.7,
+        mode: AxisMode::Roll,
+        throttle_pitch: None,
+        hold: Hold::Centre,
+        smoothing: &[StageConfig::OneEuro {
+            min_cutoff: 1.0,
+            beta: 0.1,
+        }],
+    },
+    Profile {
+        name: "truck",
+        lock_to_lock: 1080.0,
+        end_zone: 90.0,
+        end_zone_strength: 0.8,
+        mode: AxisMode::Roll,
+        throttle_pitch: None,
+        // A truck at speed should not swerve back to centre on a dropout
+        hold: Hold::Last,
+        smoothing: &[
+            StageConfig::OneEuro {
+                min_cutoff: 0.7,
+                beta: 0.08,
+            },
+            StageConfig::Slew { max_rate: 360.0 },
+        ],
+    },
+    Profile {
+        name: "tilt",
+        lock_to_lock: 120.0,
+        end_zone: 10.0,
+        end_zone_strength: 0.4,
+        mode: AxisMode::Tilt,
+        throttle_pitch: Some(30.0),
+        hold: Hold::Centre,
+        smoothing: &[StageConfig::OneEuro {
+            min_cutoff: 0.8,
+            beta: 0.05,
+        }],
+    },
+    Profile {
+        name: "grip",
+        lock_to_lock: 180.0,
+        end_zone: 15.0,
+        end_zone_strength: 0.5,
+        mode: AxisMode::Pitch,
+        throttle_pitch: None,
+        hold: Hold::Centre,
+        smoothing: &[StageConfig::OneEuro {
+            min_cutoff: 1.0,
+            beta: 0.1,
+        }],
+    },
+    Profile {
+        name: "flat",
+        lock_to_lock: 540.0,
+        end_zone: 40.0,
+        end_zone_strength: 0.6,
+        mode: AxisMode::Yaw,
+        throttle_pitch: None,
+        hold: Hold::Centre,
+        smoothing: &[StageConfig::OneEuro {
+            min_cutoff: 1.0,
+            beta: 0.1,
+        }],
+    },
+];
+
+pub const DEFAULT_PROFILE: usize = 2;
+
+impl Profile {
+    /// Maps a steering angle in degrees to the range -1.0..=1.0.
+    ///
+    /// The response is linear up to the end zone, where the slope
+    /// falls off quadratically until the output saturates at the lock.
+    pub fn steering(&self, angle: f32) -> f32 {
+        let half = self.lock_to_lock / 2.0;
+        let zone = self.end_zone.clamp(0.0, half);
+        let knee = half - zone;
+        let strength = self.end_zone_strength.clamp(0.0, 1.0);
+        let shape = |a: f32| {
+            if a <= knee || zone <= 0.0 {
+                a.min(half)
+            } else {
+                let u = a.min(half) - knee;
+                knee + u - strength * u * u / (2.0 * zone)
+            }
+        };
+        (shape(angle.abs()) / shape(half)).copysign(angle)
+    }
+
+    /// Maps the IMU pitch in degrees to a throttle from 0.0 to 1.0, if the
+    /// profile uses pitch for the throttle.
+    pub fn throttle(&self, pitch: f32) -> Option<f32> {
+        let full = self.throttle_pitch?;
+        Some((pitch / full).clamp(0.0, 1.0))
+    }
+
+    /// Whether the angle is beyond the lock.
+    pub fn past_lock(&self, angle: f32) -> bool {
+        angle.abs() > self.lock_to_lock / 2.0
+    }
+}
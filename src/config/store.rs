@@ -0,0 +1,104 @@
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::warn;
+
+const NAMESPACE: &str = "steering";
+
+/// Persistent settings kept in the default NVS partition.
+///
+/// Keys are limited to 15 characters by NVS.
+pub struct Store {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Store {
+    pub fn new() -> anyhow::Result<Self> {
+        let partition = EspDefaultNvsPartition::take()?;
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        Ok(Self { nvs })
+    }
+
+    pub fn get_u8(&self, key: &str) -> Option<u8> {
+        match self.nvs.get_u8(key) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to read {} from NVS: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    pub fn set_u8(&self, key: &str, value: u8) -> anyhow::Result<()> {
+        self.nvs.set_u8(key, value)?;
+        Ok(())
+    }
+
+    pub fn get_u16(&self, key: &str) -> Option<u16> {
+        match self.nvs.get_u16(key) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to read {} from NVS: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    pub fn set_u16(&self, key: &str, value: u16) -> anyhow::Result<()> {
+        self.nvs.set_u16(key, value)?;
+        Ok(())
+    }
+
+    /// Reads several values that only make sense together.
+    pub fn get_u16s<const N: usize>(&self, keys: [&str; N]) -> Option<[u16; N]> {
+        let mut values = [0; N];
+        for (value, key) in values.iter_mut().zip(keys) {
+            *value = self.get_u16(key)?;
+        }
+        Some(values)
+    }
+
+    pub fn set_u16s<const N: usize>(
+        &self,
+        keys: [&str; N],
+        values: [u16; N],
+    ) -> anyhow::Result<()> {
+        for (key, value) in keys.into_iter().zip(values) {
+            self.set_u16(key, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        match self.nvs.get_u32(key) {
+            Ok(value) => value.map(f32::from_bits),
+            Err(e) => {
+                warn!("Failed to read {} from NVS: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    pub fn set_f32(&self, key: &str, value: f32) -> anyhow::Result<()> {
+        self.nvs.set_u32(key, value.to_bits())?;
+        Ok(())
+    }
+
+    /// Reads several values that only make sense together, such as a vector.
+    pub fn get_f32s<const N: usize>(&self, keys: [&str; N]) -> Option<[f32; N]> {
+        let mut values = [0.0; N];
+        for (value, key) in values.iter_mut().zip(keys) {
+            *value = self.get_f32(key)?;
+        }
+        Some(values)
+    }
+
+    pub fn set_f32s<const N: usize>(
+        &self,
+        keys: [&str; N],
+        values: [f32; N],
+    ) -> anyhow::Result<()> {
+        for (key, value) in keys.into_iter().zip(values) {
+            self.set_f32(key, value)?;
+        }
+        Ok(())
+    }
+}
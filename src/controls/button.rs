@@ -0,0 +1,131 @@
+use crate::debounce::{Debounce, DEBOUNCE_TIME};
+use crate::hal::hal_error;
+use embedded_hal::digital::InputPin;
+use std::time::{Duration, Instant};
+
+/// A switch that pulls its pin low when pressed, or high with `invert`.
+pub struct Button<P: InputPin> {
+    pin: P,
+    invert: bool,
+    debounce: Debounce,
+    debounce_time: Duration,
+    read_at: Instant,
+    /// When the current press started.
+    pressed_at: Option<Instant>,
+    /// How long the last finished press lasted.
+    last_press: Option<Duration>,
+    just_pressed: bool,
+    just_released: bool,
+}
+
+impl<P: InputPin> Button<P> {
+    /// Takes the pin with its pull already set to hold it released.
+    pub fn new(pin: P, invert: bool) -> Self {
+        Self {
+            pin,
+            invert,
+            debounce: Debounce::new(),
+            debounce_time: DEBOUNCE_TIME,
+            read_at: Instant::now(),
+            pressed_at: None,
+            last_press: None,
+            just_pressed: false,
+            just_released: false,
+        }
+    }
+
+    /// Sets how long the switch has to settle before it counts as pressed
+    /// or released.
+    pub fn set_debounce(&mut self, time: Duration) {
+        self.debounce_time = time;
+    }
+
+    /// Reads the switch and returns whether it is pressed after debouncing.
+    ///
+    /// Must be called periodically, the edges are those of the last read.
+    pub fn read(&mut self) -> anyhow::Result<bool> {
+        let raw = self.pin.is_low().map_err(hal_error)? != self.invert;
+        let dt = self.read_at.elapsed().as_secs_f32();
+        self.read_at = Instant::now();
+
+        let changed = self.debounce.update(raw, dt, self.debounce_time);
+        self.just_pressed = changed == Some(true);
+        self.just_released = changed == Some(false);
+        if self.just_pressed {
+            self.pressed_at = Some(self.read_at);
+        } else if self.just_released {
+            self.last_press = self.pressed_at.take().map(|at| at.elapsed());
+        }
+        Ok(self.debounce.pressed())
+    }
+
+    /// Whether the last read found the button newly pressed.
+    pub fn just_pressed(&self) -> bool {
+        self.just_pressed
+    }
+
+    /// Whether the last read found the button newly released.
+    pub fn just_released(&self) -> bool {
+        self.just_released
+    }
+
+    /// How long the button has been held, if it is pressed.
+    pub fn held_for(&self) -> Option<Duration> {
+        self.pressed_at.map(|at| at.elapsed())
+    }
+
+    /// How long the last finished press lasted.
+    pub fn last_press(&self) -> Option<Duration> {
+        self.last_press
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock::MockPin;
+
+    #[test]
+    fn reports_edges_and_press_duration() {
+        let pin = MockPin::new(true);
+        let mut button = Button::new(pin.clone(), false);
+        button.set_debounce(Duration::ZERO);
+        assert!(!button.read().unwrap());
+        assert!(button.held_for().is_none());
+
+        pin.set_high(false);
+        assert!(button.read().unwrap());
+        assert!(button.just_pressed() && !button.just_released());
+        assert!(button.read().unwrap());
+        assert!(!button.just_pressed());
+        assert!(button.held_for().is_some());
+
+        pin.set_high(true);
+        assert!(!button.read().unwrap());
+        assert!(button.just_released() && !button.just_pressed());
+        assert!(button.held_for().is_none());
+        assert!(button.last_press().is_some());
+    }
+
+    #[test]
+    fn inverted_button_is_pressed_high() {
+        let pin = MockPin::new(false);
+        let mut button = Button::new(pin.clone(), true);
+        button.set_debounce(Duration::ZERO);
+        assert!(!button.read().unwrap());
+        pin.set_high(true);
+        assert!(button.read().unwrap());
+    }
+
+    #[test]
+    fn absorbs_a_bounce() {
+        let pin = MockPin::new(true);
+        let mut button = Button::new(pin.clone(), false);
+        button.set_debounce(Duration::from_secs(1));
+        for high in [false, true, false, true] {
+            pin.set_high(high);
+            assert!(!button.read().unwrap());
+            assert!(!button.just_pressed());
+        }
+    }
+}
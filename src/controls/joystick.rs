@@ -0,0 +1,367 @@
+use super::StickResponse;
+use crate::hal::{hal_error, AnalogInput};
+use embedded_hal::digital::InputPin;
+
+/// Samples averaged for the centre.
+const CENTRE_SAMPLES: u16 = 10;
+/// Travel each axis needs on both sides of the centre for a calibration to
+/// be taken, in mV.
+const MIN_TRAVEL: u16 = 500;
+
+/// Raw readings of one axis at its ends and centre, in mV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AxisRange {
+    pub min: u16,
+    pub mid: u16,
+    pub max: u16,
+}
+
+impl AxisRange {
+    /// The range assumed without a calibration, around a sampled centre.
+    fn around(mid: u16) -> Self {
+        Self {
+            min: 200, // 0.2 V
+            mid,
+            max: 3100, // 3.1 V
+        }
+    }
+
+    fn valid(&self) -> bool {
+        self.mid >= self.min.saturating_add(MIN_TRAVEL)
+            && self.max >= self.mid.saturating_add(MIN_TRAVEL)
+    }
+}
+
+/// Ranges of both axes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoystickCalibration {
+    pub x: AxisRange,
+    pub y: AxisRange,
+}
+
+impl JoystickCalibration {
+    /// Whether both axes travel far enough either side of their centre.
+    pub fn valid(&self) -> bool {
+        self.x.valid() && self.y.valid()
+    }
+}
+
+/// The outcome of a calibration capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoystickCapture {
+    /// Sweeping: move the stick around its full range, then release it and
+    /// capture again.
+    Sweep,
+    /// Both captures done, and the calibration applied.
+    Done(JoystickCalibration),
+    /// An axis did not travel far enough from the centre, start over.
+    RangeTooSmall,
+}
+
+pub struct Joystick<X: AnalogInput, Y: AnalogInput, BTN: InputPin> {
+    x_adc: X,
+    y_adc: Y,
+    button: BTN,
+    response: StickResponse,
+    calibration: JoystickCalibration,
+    /// Lowest and highest readings of each axis while sweeping.
+    sweep: Option<[(u16, u16); 2]>,
+    output_min: i16,
+    output_max: i16,
+}
+
+impl<X: AnalogInput, Y: AnalogInput, BTN: InputPin> Joystick<X, Y, BTN> {
+    /// Takes the axes, in mV, and the pulled-up button pin. Without a
+    /// calibration, samples the axes for the centre, so the stick has to be
+    /// left alone meanwhile.
+    pub fn new(
+        x_adc: X,
+        y_adc: Y,
+        button: BTN,
+        calibration: Option<JoystickCalibration>,
+        response: StickResponse,
+        output_min: i16,
+        output_max: i16,
+    ) -> anyhow::Result<Self> {
+        let mut res = Self {
+            x_adc,
+            y_adc,
+            button,
+            response,
+            calibration: JoystickCalibration {
+                x: AxisRange::around(0),
+                y: AxisRange::around(0),
+            },
+            sweep: None,
+            output_min,
+            output_max,
+        };
+        res.calibration = match calibration {
+            Some(calibration) => calibration,
+            None => {
+                let (x_mid, y_mid) = res.sample_centre()?;
+                JoystickCalibration {
+                    x: AxisRange::around(x_mid),
+                    y: AxisRange::around(y_mid),
+                }
+            }
+        };
+        Ok(res)
+    }
+
+    /// Averages a few readings of the axes.
+    fn sample_centre(&mut self) -> anyhow::Result<(u16, u16)> {
+        let mut x_sum: u32 = 0;
+        let mut y_sum: u32 = 0;
+        for _ in 0..CENTRE_SAMPLES {
+            x_sum += u32::from(self.x_adc.read()?);
+            y_sum += u32::from(self.y_adc.read()?);
+        }
+        let n = u32::from(CENTRE_SAMPLES);
+        Ok(((x_sum / n) as u16, (y_sum / n) as u16))
+    }
+
+    pub fn response(&self) -> StickResponse {
+        self.response
+    }
+
+    /// Sets the deadzones and curves, applied after the calibration.
+    pub fn set_response(&mut self, response: StickResponse) {
+        self.response = response;
+    }
+
+    pub fn calibration(&self) -> JoystickCalibration {
+        self.calibration
+    }
+
+    /// Whether a calibration has been started and waits for its second
+    /// capture.
+    pub fn calibrating(&self) -> bool {
+        self.sweep.is_some()
+    }
+
+    /// Takes the next calibration capture: the first starts sweeping for
+    /// the ends of the axes, the second takes the centre, with the stick
+    /// released, and applies the calibration if the axes travelled far
+    /// enough.
+    pub fn calibrate(&mut self) -> anyhow::Result<JoystickCapture> {
+        let Some([(x_min, x_max), (y_min, y_max)]) = self.sweep.take() else {
+            self.sweep = Some([(u16::MAX, 0); 2]);
+            return Ok(JoystickCapture::Sweep);
+        };
+        let (x_mid, y_mid) = self.sample_centre()?;
+        let calibration = JoystickCalibration {
+            x: AxisRange {
+                min: x_min,
+                mid: x_mid,
+                max: x_max,
+            },
+            y: AxisRange {
+                min: y_min,
+                mid: y_mid,
+                max: y_max,
+            },
+        };
+        if !calibration.valid() {
+            return Ok(JoystickCapture::RangeTooSmall);
+        }
+        self.calibration = calibration;
+        Ok(JoystickCapture::Done(calibration))
+    }
+
+    pub fn read(&mut self) -> anyhow::Result<(i16, i16, bool)> {
+        let x_val = self.x_adc.read()?;
+        let y_val = self.y_adc.read()?;
+        let btn_pressed = self.button.is_low().map_err(hal_error)?;
+
+        if let Some([x, y]) = self.sweep.as_mut() {
+            *x = (x.0.min(x_val), x.1.max(x_val));
+            *y = (y.0.min(y_val), y.1.max(y_val));
+        }
+
+        // map the joystick values to -1.0..1.0, each side of the centre
+        // to its half
+        let normalize = |value: u16, range: &AxisRange| {
+            let value = value as f32;
+            let mid = range.mid as f32;
+            let value = if value > mid {
+                (value - mid) / (range.max as f32 - mid)
+            } else {
+                (value - mid) / (mid - range.min as f32)
+            };
+            value.clamp(-1.0, 1.0)
+        };
+        let (x_val, y_val) = self.response.apply(
+            normalize(x_val, &self.calibration.x),
+            normalize(y_val, &self.calibration.y),
+        );
+
+        let output_min = self.output_min as f32;
+        let output_max = self.output_max as f32;
+        let output_mid = (output_max + output_min) / 2.0;
+        let scale = |value: f32| output_mid + value * (output_max - output_min) / 2.0;
+
+        Ok((scale(x_val) as i16, scale(y_val) as i16, btn_pressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock::{MockAnalog, MockPin};
+
+    const MAX: i16 = 32767;
+
+    fn joystick() -> (
+        Joystick<MockAnalog, MockAnalog, MockPin>,
+        MockAnalog,
+        MockPin,
+    ) {
+        let x = MockAnalog::new(1650);
+        let button = MockPin::new(true);
+        let joystick = Joystick::new(
+            x.clone(),
+            MockAnalog::new(1650),
+            button.clone(),
+            None,
+            StickResponse::default(),
+            -MAX,
+            MAX,
+        )
+        .unwrap();
+        (joystick, x, button)
+    }
+
+    #[test]
+    fn centre_reads_zero() {
+        let (mut joystick, _, _) = joystick();
+        assert_eq!(joystick.read().unwrap(), (0, 0, false));
+    }
+
+    #[test]
+    fn maps_each_side_to_its_half() {
+        let (mut joystick, x, _) = joystick();
+        x.set(3100);
+        assert_eq!(joystick.read().unwrap().0, MAX);
+        x.set(200);
+        assert_eq!(joystick.read().unwrap().0, -MAX);
+        // Halfway between the centre and the end of each side
+        x.set(2375);
+        assert!((joystick.read().unwrap().0 - MAX / 2).abs() <= 1);
+        x.set(925);
+        assert!((joystick.read().unwrap().0 + MAX / 2).abs() <= 1);
+    }
+
+    #[test]
+    fn deadzone_holds_centre() {
+        let (mut joystick, x, _) = joystick();
+        let mut response = StickResponse::default();
+        response.x.deadzone = 0.05;
+        response.y.deadzone = 0.05;
+        joystick.set_response(response);
+        // 5% is about 70 mV on the high side
+        x.set(1710);
+        assert_eq!(joystick.read().unwrap().0, 0);
+        x.set(1800);
+        assert!(joystick.read().unwrap().0 > 0);
+    }
+
+    #[test]
+    fn clamps_past_the_ends() {
+        let (mut joystick, x, _) = joystick();
+        x.set(3300);
+        assert_eq!(joystick.read().unwrap().0, MAX);
+        x.set(0);
+        assert_eq!(joystick.read().unwrap().0, -MAX);
+    }
+
+    #[test]
+    fn button_is_active_low() {
+        let (mut joystick, _, button) = joystick();
+        button.set_high(false);
+        assert!(joystick.read().unwrap().2);
+    }
+
+    #[test]
+    fn applies_a_stored_calibration() {
+        let x = MockAnalog::new(2000);
+        let range = AxisRange {
+            min: 1000,
+            mid: 2000,
+            max: 3000,
+        };
+        let calibration = JoystickCalibration { x: range, y: range };
+        // The stick is off centre at boot, but the centre is not sampled
+        let mut joystick = Joystick::new(
+            x.clone(),
+            MockAnalog::new(2500),
+            MockPin::new(true),
+            Some(calibration),
+            StickResponse::default(),
+            -MAX,
+            MAX,
+        )
+        .unwrap();
+        assert_eq!(joystick.calibration(), calibration);
+        assert_eq!(joystick.read().unwrap().0, 0);
+        x.set(3000);
+        assert_eq!(joystick.read().unwrap().0, MAX);
+        x.set(1000);
+        assert_eq!(joystick.read().unwrap().0, -MAX);
+    }
+
+    #[test]
+    fn calibrates_from_a_sweep_and_the_centre() {
+        let x = MockAnalog::new(1650);
+        let y = MockAnalog::new(1650);
+        let mut joystick = Joystick::new(
+            x.clone(),
+            y.clone(),
+            MockPin::new(true),
+            None,
+            StickResponse::default(),
+            -MAX,
+            MAX,
+        )
+        .unwrap();
+        assert_eq!(joystick.calibrate().unwrap(), JoystickCapture::Sweep);
+        assert!(joystick.calibrating());
+        for value in [1650, 400, 2900, 1650] {
+            x.set(value);
+            y.set(3300 - value);
+            joystick.read().unwrap();
+        }
+        x.set(1600);
+        let JoystickCapture::Done(calibration) = joystick.calibrate().unwrap() else {
+            panic!("calibration not done");
+        };
+        assert!(!joystick.calibrating());
+        assert_eq!(
+            calibration.x,
+            AxisRange {
+                min: 400,
+                mid: 1600,
+                max: 2900,
+            }
+        );
+        x.set(2900);
+        assert_eq!(joystick.read().unwrap().0, MAX);
+    }
+
+    #[test]
+    fn rejects_a_short_sweep() {
+        let (mut joystick, x, _) = joystick();
+        let before = joystick.calibration();
+        joystick.calibrate().unwrap();
+        x.set(2900);
+        joystick.read().unwrap();
+        x.set(1650);
+        // The Y axis never moved
+        assert_eq!(
+            joystick.calibrate().unwrap(),
+            JoystickCapture::RangeTooSmall
+        );
+        assert_eq!(joystick.calibration(), before);
+        assert!(!joystick.calibrating());
+    }
+}
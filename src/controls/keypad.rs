@@ -0,0 +1,338 @@
+use crate::debounce::{Debounce, DEBOUNCE_TIME};
+use crate::hal::hal_error;
+use bitflags::bitflags;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Events kept for consumers that fall behind, oldest dropped first.
+const MAX_EVENTS: usize = 32;
+/// Keys that fit the state bits, enough for an 8x8 matrix.
+pub const MAX_KEYS: usize = 64;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct KeyState: u16 {
+        const PRESSED     = 1 << 0;
+        const JUST_PRESS  = 1 << 1;
+        const JUST_RELEASE= 1 << 2;
+    }
+}
+
+/// HID button bit of each key, by row and column, so that the buttons do
+/// not have to follow the wiring of the matrix. `None` leaves a key out of
+/// the report.
+pub type KeyMap<const CS: usize, const RS: usize> = [[Option<u8>; CS]; RS];
+
+/// Describes the hardware-level matrix of switches.
+///
+/// Generic parameters are in order: The type of column pins,
+/// the type of row pins, the delay, the number of columns and rows.
+/// **NOTE:** In order to be able to put different pin structs
+/// in an array they have to be downgraded (stripped of their
+/// numbers etc.). Most HAL-s have a method of downgrading pins
+/// to a common (erased) struct. (for example see
+/// [stm32f0xx_hal::gpio::PA0::downgrade](https://docs.rs/stm32f0xx-hal/0.17.1/stm32f0xx_hal/gpio/gpioa/struct.PA0.html#method.downgrade))
+pub struct Keypad<C, R, D, const CS: usize, const RS: usize> {
+    cols: [C; CS],
+    rows: [R; RS],
+    delay: D,
+    settle_us: u32,
+    /// Whether each switch has a diode, so that any set of keys can be told
+    /// apart.
+    has_diodes: bool,
+    /// The row scanned next.
+    row: usize,
+    /// Raw levels of the rows scanned so far in this pass.
+    raw_states: u64,
+    states: u64,
+    debounce: [[Debounce; CS]; RS],
+    debounce_time: Duration,
+    scanned_at: Instant,
+    events: VecDeque<(usize, usize, KeyState)>,
+}
+
+impl<C, R, D, const CS: usize, const RS: usize> Keypad<C, R, D, CS, RS>
+where
+    C: InputPin,
+    R: OutputPin,
+    D: DelayNs,
+{
+    /// Fails the build for matrices with more keys than state bits.
+    const FITS: () = assert!(CS * RS <= MAX_KEYS, "keypad matrix larger than 8x8");
+
+    /// Creates a new Matrix.
+    ///
+    /// Assumes columns are pull-up inputs,
+    /// and rows are output pins which are set high when not being scanned.
+    /// `settle_us` is how long the columns are given to settle after a row
+    /// is pulled low. Without `has_diodes`, keys that cannot be told apart
+    /// from a ghost are held off instead of being reported.
+    pub fn new(
+        cols: [C; CS],
+        rows: [R; RS],
+        delay: D,
+        settle_us: u32,
+        has_diodes: bool,
+    ) -> anyhow::Result<Self> {
+        let () = Self::FITS;
+        let mut res = Self {
+            cols,
+            rows,
+            delay,
+            settle_us,
+            has_diodes,
+            row: 0,
+            raw_states: 0,
+            states: 0,
+            debounce: [[Debounce::new(); CS]; RS],
+            debounce_time: DEBOUNCE_TIME,
+            scanned_at: Instant::now(),
+            events: VecDeque::new(),
+        };
+        res.clear()?;
+        Ok(res)
+    }
+
+    fn clear(&mut self) -> anyhow::Result<()> {
+        for r in self.rows.iter_mut() {
+            r.set_high().map_err(hal_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets how long a key has to settle before it counts as pressed or
+    /// released.
+    pub fn set_debounce(&mut self, time: Duration) {
+        self.debounce_time = time;
+    }
+
+    /// Debounces the raw levels of a scan, and queues an event for each
+    /// key that changed.
+    fn update_states(&mut self, raw_states: u64) {
+        let dt = self.scanned_at.elapsed().as_secs_f32();
+        self.scanned_at = Instant::now();
+
+        for (row, keys) in self.debounce.iter_mut().enumerate() {
+            for (col, key) in keys.iter_mut().enumerate() {
+                let mask = 1u64 << (row * CS + col);
+                let Some(pressed) = key.update(raw_states & mask != 0, dt, self.debounce_time)
+                else {
+                    continue;
+                };
+
+                let event = if pressed {
+                    self.states |= mask;
+                    KeyState::PRESSED | KeyState::JUST_PRESS
+                } else {
+                    self.states &= !mask;
+                    KeyState::JUST_RELEASE
+                };
+                if self.events.len() == MAX_EVENTS {
+                    self.events.pop_front();
+                }
+                self.events.push_back((row, col, event));
+            }
+        }
+    }
+
+    /// Scans the next row of the matrix, and debounces the keys once
+    /// all rows have been scanned.
+    ///
+    /// The row pin is pulled low, and after `settle_us` each column pin is
+    /// tested; if it's low, the key is marked as pressed. Meant to be called
+    /// on a steady tick of its own, so that a full scan takes `RS` ticks
+    /// without holding up anything else for more than a few microseconds.
+    pub fn scan_row(&mut self) -> anyhow::Result<()> {
+        let row_idx = self.row;
+        let row_pin = &mut self.rows[row_idx];
+        row_pin.set_low().map_err(hal_error)?;
+        self.delay.delay_us(self.settle_us);
+
+        for (col_idx, col_pin) in self.cols.iter_mut().enumerate() {
+            let key_idx = row_idx * CS + col_idx;
+
+            if col_pin.is_low().map_err(hal_error)? {
+                // press
+                self.raw_states |= 1u64 << key_idx;
+            } else {
+                // release
+                self.raw_states &= !(1u64 << key_idx);
+            }
+        }
+
+        row_pin.set_high().map_err(hal_error)?;
+
+        self.row = (row_idx + 1) % RS;
+        if self.row == 0 {
+            let raw_states = if self.has_diodes {
+                self.raw_states
+            } else {
+                // Keys already held stay held, new ones wait until the
+                // rectangle breaks up
+                self.raw_states & (!Self::ambiguous(self.raw_states) | self.states)
+            };
+            self.update_states(raw_states);
+        }
+        Ok(())
+    }
+
+    /// Bits of the keys at the corners of a rectangle of pressed keys.
+    ///
+    /// Without diodes, holding three corners of a rectangle connects the
+    /// fourth row and column through them, so the fourth key reads pressed
+    /// too; none of the four can then be trusted.
+    fn ambiguous(raw_states: u64) -> u64 {
+        let row_mask = 1u64.checked_shl(CS as u32).map_or(u64::MAX, |bit| bit - 1);
+        let row_bits = |row: usize| (raw_states >> (row * CS)) & row_mask;
+        let mut ambiguous = 0;
+        for a in 0..RS {
+            for b in a + 1..RS {
+                let shared = row_bits(a) & row_bits(b);
+                if shared.count_ones() >= 2 {
+                    ambiguous |= shared << (a * CS) | shared << (b * CS);
+                }
+            }
+        }
+        ambiguous
+    }
+
+    /// Bits of the keys pressed after debouncing, by `row * CS + col`.
+    pub fn states(&self) -> u64 {
+        self.states
+    }
+
+    /// Bits of the HID buttons of the keys pressed, through `map`.
+    pub fn buttons(&self, map: &KeyMap<CS, RS>) -> u64 {
+        let mut buttons = 0;
+        for (row, bits) in map.iter().enumerate() {
+            for (col, bit) in bits.iter().enumerate() {
+                if let Some(bit) = bit {
+                    if self.states & 1u64 << (row * CS + col) != 0 {
+                        buttons |= 1u64.checked_shl(u32::from(*bit)).unwrap_or(0);
+                    }
+                }
+            }
+        }
+        buttons
+    }
+
+    /// The state of one key, without its edges.
+    pub fn key_state(&self, row: usize, col: usize) -> KeyState {
+        match self.debounce.get(row).and_then(|keys| keys.get(col)) {
+            Some(key) if key.pressed() => KeyState::PRESSED,
+            _ => KeyState::empty(),
+        }
+    }
+
+    /// Takes the key events since the last call, oldest first, as
+    /// `(row, col, state)` with `JUST_PRESS` or `JUST_RELEASE` set.
+    pub fn events(&mut self) -> impl Iterator<Item = (usize, usize, KeyState)> + '_ {
+        self.events.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock::{MockCol, MockDelay, MockMatrix, MockRow};
+
+    type MockKeypad = Keypad<MockCol, MockRow, MockDelay, 4, 4>;
+
+    fn keypad(diodes: bool) -> (MockKeypad, MockMatrix) {
+        let matrix = MockMatrix::new(diodes);
+        let mut keypad = Keypad::new(
+            std::array::from_fn(|col| matrix.col(col)),
+            std::array::from_fn(|row| matrix.row(row)),
+            MockDelay,
+            10,
+            diodes,
+        )
+        .unwrap();
+        keypad.set_debounce(Duration::ZERO);
+        (keypad, matrix)
+    }
+
+    fn scan(keypad: &mut MockKeypad) {
+        for _ in 0..4 {
+            keypad.scan_row().unwrap();
+        }
+    }
+
+    #[test]
+    fn reports_pressed_keys_and_events() {
+        let (mut keypad, matrix) = keypad(false);
+        matrix.press(1, 2);
+        scan(&mut keypad);
+        assert_eq!(keypad.states(), 1 << 6);
+        assert!(keypad.key_state(1, 2).contains(KeyState::PRESSED));
+        let events: Vec<_> = keypad.events().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, 1);
+        assert_eq!(events[0].1, 2);
+        assert!(events[0].2.contains(KeyState::JUST_PRESS));
+
+        matrix.release(1, 2);
+        scan(&mut keypad);
+        assert_eq!(keypad.states(), 0);
+        let events: Vec<_> = keypad.events().collect();
+        assert!(events[0].2.contains(KeyState::JUST_RELEASE));
+    }
+
+    #[test]
+    fn waits_for_the_last_row() {
+        let (mut keypad, matrix) = keypad(false);
+        matrix.press(0, 0);
+        keypad.scan_row().unwrap();
+        assert_eq!(keypad.states(), 0);
+        for _ in 1..4 {
+            keypad.scan_row().unwrap();
+        }
+        assert_eq!(keypad.states(), 1);
+    }
+
+    #[test]
+    fn maps_keys_to_buttons() {
+        let (mut keypad, matrix) = keypad(false);
+        let mut map: KeyMap<4, 4> = [[None; 4]; 4];
+        map[0][1] = Some(40);
+        map[3][3] = Some(2);
+        matrix.press(0, 1);
+        matrix.press(3, 3);
+        matrix.press(2, 2);
+        scan(&mut keypad);
+        assert_eq!(keypad.buttons(&map), 1 << 40 | 1 << 2);
+    }
+
+    #[test]
+    fn suppresses_ghost_without_diodes() {
+        let (mut keypad, matrix) = keypad(false);
+        matrix.press(0, 0);
+        matrix.press(0, 1);
+        scan(&mut keypad);
+        matrix.press(1, 0);
+        scan(&mut keypad);
+        // (1, 1) reads pressed through the other three, and (1, 0) can not be
+        // told from it
+        assert_eq!(keypad.states(), 1 | 1 << 1);
+
+        matrix.release(0, 1);
+        scan(&mut keypad);
+        assert_eq!(keypad.states(), 1 | 1 << 4);
+    }
+
+    #[test]
+    fn rolls_over_with_diodes() {
+        let (mut keypad, matrix) = keypad(true);
+        matrix.press(0, 0);
+        matrix.press(0, 1);
+        matrix.press(1, 0);
+        scan(&mut keypad);
+        assert_eq!(keypad.states(), 1 | 1 << 1 | 1 << 4);
+        matrix.press(1, 1);
+        scan(&mut keypad);
+        assert_eq!(keypad.states(), 1 | 1 << 1 | 1 << 4 | 1 << 5);
+    }
+}
@@ -0,0 +1,16 @@
+//! Drivers of the physical controls, over the traits in [`crate::hal`].
+
+mod button;
+pub use button::*;
+
+mod joystick;
+pub use joystick::*;
+
+mod keypad;
+pub use keypad::*;
+
+mod pedal;
+pub use pedal::*;
+
+mod response;
+pub use response::*;
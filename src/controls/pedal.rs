@@ -0,0 +1,97 @@
+use crate::hal::AnalogInput;
+// use log::info;
+
+pub struct Pedal<X: AnalogInput, Y: AnalogInput> {
+    accelerator_adc: X,
+    brake_adc: Y,
+    input_min: u16,
+    input_max: u16,
+    output_min: i16,
+    output_max: i16,
+}
+
+impl<X: AnalogInput, Y: AnalogInput> Pedal<X, Y> {
+    /// Takes the accelerator and brake inputs, in mV.
+    pub fn new(
+        accelerator_adc: X,
+        brake_adc: Y,
+        deadzone: u16, // Deadzone value in mV
+        output_min: i16,
+        output_max: i16,
+    ) -> Self {
+        Self {
+            accelerator_adc,
+            brake_adc,
+            input_min: 150 + deadzone, // 0.15V
+            input_max: 2450,           // 2.45V
+            output_min,
+            output_max,
+        }
+    }
+
+    pub fn read(&mut self) -> anyhow::Result<(i16, i16)> {
+        let accelerator_val = self.accelerator_adc.read()?;
+        let brake_val = self.brake_adc.read()?;
+
+        let mut accelerator_val = accelerator_val as f32;
+        let mut brake_val = brake_val as f32;
+        let input_min = self.input_min as f32;
+        let input_max = self.input_max as f32;
+        let output_min = self.output_min as f32;
+        let output_max = self.output_max as f32;
+
+        accelerator_val = accelerator_val.clamp(input_min, input_max);
+        brake_val = brake_val.clamp(input_min, input_max);
+
+        // map the Pedal values to a range
+        accelerator_val = (accelerator_val - input_min) / (input_max - input_min)
+            * (output_max - output_min)
+            + output_min;
+        brake_val = (brake_val - input_min) / (input_max - input_min) * (output_max - output_min)
+            + output_min;
+
+        Ok((accelerator_val as i16, brake_val as i16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock::MockAnalog;
+
+    const MAX: i16 = 32767;
+
+    fn pedal() -> (Pedal<MockAnalog, MockAnalog>, MockAnalog, MockAnalog) {
+        let accelerator = MockAnalog::new(0);
+        let brake = MockAnalog::new(0);
+        let pedal = Pedal::new(accelerator.clone(), brake.clone(), 700, 0, MAX);
+        (pedal, accelerator, brake)
+    }
+
+    #[test]
+    fn released_within_deadzone() {
+        let (mut pedal, accelerator, brake) = pedal();
+        assert_eq!(pedal.read().unwrap(), (0, 0));
+        accelerator.set(850);
+        brake.set(500);
+        assert_eq!(pedal.read().unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn maps_travel_linearly() {
+        let (mut pedal, accelerator, brake) = pedal();
+        accelerator.set(2450);
+        brake.set(1650);
+        let (accelerator, brake) = pedal.read().unwrap();
+        assert_eq!(accelerator, MAX);
+        assert!((brake - MAX / 2).abs() <= 1);
+    }
+
+    #[test]
+    fn clamps_past_full_travel() {
+        let (mut pedal, accelerator, brake) = pedal();
+        accelerator.set(3300);
+        brake.set(u16::MAX);
+        assert_eq!(pedal.read().unwrap(), (MAX, MAX));
+    }
+}
@@ -0,0 +1,256 @@
+/// Points of a custom response curve.
+pub const CURVE_POINTS: usize = 5;
+
+/// How the deadzone of a stick is shaped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadzoneMode {
+    /// Each axis has its own deadzone, a cross around the centre. Keeps
+    /// the axes apart, but pulls diagonals towards them.
+    Axial,
+    /// The deadzone is a circle, or an ellipse if the axes differ, and the
+    /// stick direction is kept.
+    Radial,
+}
+
+impl DeadzoneMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(DeadzoneMode::Axial),
+            1 => Some(DeadzoneMode::Radial),
+            _ => None,
+        }
+    }
+}
+
+/// Response to the stick deflection past the deadzone, both from 0.0 to 1.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// The deflection raised to a power: above 1.0 for finer control near
+    /// the centre, below for quicker response.
+    Exponential(f32),
+    /// Blends in, by the given weight from 0.0 to 1.0, a curve that is fine
+    /// at both the centre and the ends and quick in between.
+    SCurve(f32),
+    /// Outputs at evenly spaced deflections from 0.0 to 1.0, joined by
+    /// straight lines.
+    Points([f32; CURVE_POINTS]),
+}
+
+impl Curve {
+    pub fn apply(&self, value: f32) -> f32 {
+        let value = value.clamp(0.0, 1.0);
+        match *self {
+            Curve::Linear => value,
+            Curve::Exponential(exponent) => value.powf(exponent),
+            Curve::SCurve(weight) => {
+                let smooth = value * value * (3.0 - 2.0 * value);
+                value + (smooth - value) * weight
+            }
+            Curve::Points(points) => {
+                let position = value * (CURVE_POINTS - 1) as f32;
+                let i = (position as usize).min(CURVE_POINTS - 2);
+                let t = position - i as f32;
+                points[i] + (points[i + 1] - points[i]) * t
+            }
+        }
+    }
+}
+
+/// How one axis responds to the stick, as fractions of full deflection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisResponse {
+    /// Deflection around the centre that reads as centred.
+    pub deadzone: f32,
+    /// Deflection short of the ends that already reads as full.
+    pub outer: f32,
+    /// Output the axis jumps to as it leaves the deadzone, to cancel out a
+    /// deadzone of the game.
+    pub anti_deadzone: f32,
+    pub curve: Curve,
+}
+
+impl Default for AxisResponse {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.0,
+            outer: 0.0,
+            anti_deadzone: 0.0,
+            curve: Curve::Linear,
+        }
+    }
+}
+
+impl AxisResponse {
+    /// Maps a deflection from 0.0 to 1.0 to the output, from 0.0 to 1.0.
+    fn apply(&self, deflection: f32) -> f32 {
+        if deflection <= self.deadzone {
+            return 0.0;
+        }
+        let span = (1.0 - self.deadzone - self.outer).max(f32::EPSILON);
+        let value = self.curve.apply((deflection - self.deadzone) / span);
+        self.anti_deadzone + (1.0 - self.anti_deadzone) * value
+    }
+}
+
+/// How a two-axis stick responds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StickResponse {
+    pub mode: DeadzoneMode,
+    pub x: AxisResponse,
+    pub y: AxisResponse,
+}
+
+impl Default for StickResponse {
+    fn default() -> Self {
+        Self {
+            mode: DeadzoneMode::Radial,
+            x: AxisResponse::default(),
+            y: AxisResponse::default(),
+        }
+    }
+}
+
+impl StickResponse {
+    /// Shapes a stick position, each axis from -1.0 to 1.0.
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = match self.mode {
+            DeadzoneMode::Axial => (
+                self.x.apply(x.abs()).copysign(x),
+                self.y.apply(y.abs()).copysign(y),
+            ),
+            DeadzoneMode::Radial => {
+                let radius = x.hypot(y);
+                // Radius in units of the deadzone along the stick direction
+                let inside = (x / self.x.deadzone.max(f32::EPSILON))
+                    .hypot(y / self.y.deadzone.max(f32::EPSILON));
+                if inside <= 1.0 || radius <= 0.0 {
+                    return (0.0, 0.0);
+                }
+                // The deadzone ends at this radius along the stick direction
+                let edge = radius / inside;
+                // Past full deflection, in the corners of a square gate,
+                // only the direction changes
+                let reach = radius.min(1.0);
+                let scale = |response: &AxisResponse| {
+                    let radial = AxisResponse {
+                        deadzone: edge,
+                        ..*response
+                    };
+                    radial.apply(reach) / reach
+                };
+                (x * scale(&self.x), y * scale(&self.y))
+            }
+        };
+        (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    fn with_axes(mode: DeadzoneMode, axis: AxisResponse) -> StickResponse {
+        StickResponse {
+            mode,
+            x: axis,
+            y: axis,
+        }
+    }
+
+    #[test]
+    fn default_passes_through() {
+        let response = StickResponse::default();
+        for (x, y) in [(0.0, 0.0), (0.3, -0.7), (-1.0, 1.0), (0.5, 0.0)] {
+            let (out_x, out_y) = response.apply(x, y);
+            assert!(close(out_x, x) && close(out_y, y), "{x} {y}");
+        }
+    }
+
+    #[test]
+    fn axial_deadzone_rescales_past_it() {
+        let axis = AxisResponse {
+            deadzone: 0.2,
+            ..Default::default()
+        };
+        let response = with_axes(DeadzoneMode::Axial, axis);
+        assert_eq!(response.apply(0.15, -0.1), (0.0, 0.0));
+        let (x, y) = response.apply(0.6, 0.1);
+        assert!(close(x, 0.5));
+        assert_eq!(y, 0.0);
+        assert!(close(response.apply(-1.0, 0.0).0, -1.0));
+    }
+
+    #[test]
+    fn radial_deadzone_keeps_direction() {
+        let axis = AxisResponse {
+            deadzone: 0.2,
+            ..Default::default()
+        };
+        let response = with_axes(DeadzoneMode::Radial, axis);
+        // Inside the circle, though each axis is past an axial deadzone
+        assert_eq!(response.apply(0.13, 0.13), (0.0, 0.0));
+        let (x, y) = response.apply(0.5, 0.5);
+        assert!(close(x, y));
+        let radius = 0.5f32.hypot(0.5);
+        assert!(close(x.hypot(y), (radius - 0.2) / 0.8));
+        // A small Y offset is not swallowed as it is axially
+        let (_, y) = response.apply(0.8, 0.1);
+        assert!(y > 0.0);
+    }
+
+    #[test]
+    fn outer_deadzone_reaches_full_early() {
+        let axis = AxisResponse {
+            outer: 0.1,
+            ..Default::default()
+        };
+        let response = with_axes(DeadzoneMode::Axial, axis);
+        assert_eq!(response.apply(0.9, -0.95), (1.0, -1.0));
+        assert!(close(response.apply(0.45, 0.0).0, 0.5));
+    }
+
+    #[test]
+    fn anti_deadzone_offsets_the_output() {
+        let axis = AxisResponse {
+            deadzone: 0.1,
+            anti_deadzone: 0.25,
+            ..Default::default()
+        };
+        let response = with_axes(DeadzoneMode::Axial, axis);
+        assert_eq!(response.apply(0.05, 0.0).0, 0.0);
+        assert!(close(response.apply(0.1001, 0.0).0, 0.25));
+        assert!(close(response.apply(-1.0, 0.0).0, -1.0));
+    }
+
+    #[test]
+    fn curves_keep_their_ends() {
+        let curves = [
+            Curve::Linear,
+            Curve::Exponential(2.0),
+            Curve::SCurve(1.0),
+            Curve::Points([0.0, 0.1, 0.3, 0.6, 1.0]),
+        ];
+        for curve in curves {
+            assert!(close(curve.apply(0.0), 0.0), "{curve:?}");
+            assert!(close(curve.apply(1.0), 1.0), "{curve:?}");
+        }
+    }
+
+    #[test]
+    fn curves_shape_the_middle() {
+        assert!(close(Curve::Exponential(2.0).apply(0.5), 0.25));
+        // Fine near the ends, quick in between
+        let s_curve = Curve::SCurve(1.0);
+        assert!(s_curve.apply(0.1) < 0.1);
+        assert!(s_curve.apply(0.9) > 0.9);
+        assert!(close(s_curve.apply(0.5), 0.5));
+        let points = Curve::Points([0.0, 0.1, 0.3, 0.6, 1.0]);
+        assert!(close(points.apply(0.25), 0.1));
+        assert!(close(points.apply(0.375), 0.2));
+    }
+}
@@ -0,0 +1,111 @@
+//! Contact debouncing for keys and buttons.
+
+use std::time::Duration;
+
+/// Default time a contact has to settle before its state changes.
+pub const DEBOUNCE_TIME: Duration = Duration::from_millis(10);
+
+/// Debounces one contact with an integrator: every sample moves it towards
+/// the raw level, and the state only changes once it has gone all the way,
+/// so single bounces are absorbed without adding a fixed delay to each edge.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Debounce {
+    pressed: bool,
+    /// Seconds towards pressed, from 0.0 to the debounce time.
+    level: f32,
+}
+
+impl Debounce {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the raw contact level `dt` seconds after the previous one, and
+    /// returns the new state if it changed.
+    pub fn update(&mut self, raw: bool, dt: f32, time: Duration) -> Option<bool> {
+        let time = time.as_secs_f32();
+        self.level = if raw {
+            (self.level + dt).min(time)
+        } else {
+            (self.level - dt).max(0.0)
+        };
+        let pressed = match raw {
+            true if self.level >= time => true,
+            false if self.level <= 0.0 => false,
+            _ => self.pressed,
+        };
+        if pressed == self.pressed {
+            return None;
+        }
+        self.pressed = pressed;
+        Some(pressed)
+    }
+
+    /// The debounced state.
+    pub fn pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    /// Feeds `raw` for `time` seconds, and returns the state changes with
+    /// the time into the stretch at which each came.
+    fn hold(debounce: &mut Debounce, raw: bool, time: f32) -> Vec<(f32, bool)> {
+        (1..=(time / DT).round() as u32)
+            .filter_map(|i| {
+                let change = debounce.update(raw, DT, DEBOUNCE_TIME)?;
+                Some((i as f32 * DT, change))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn absorbs_bounces_shorter_than_the_time() {
+        let mut debounce = Debounce::new();
+        for _ in 0..5 {
+            assert!(hold(&mut debounce, true, 0.003).is_empty());
+            assert!(hold(&mut debounce, false, 0.004).is_empty());
+        }
+        assert!(!debounce.pressed());
+    }
+
+    #[test]
+    fn steady_press_registers_after_the_time() {
+        let mut debounce = Debounce::new();
+        let changes = hold(&mut debounce, true, 0.05);
+        let time = DEBOUNCE_TIME.as_secs_f32();
+        assert_eq!(changes.len(), 1, "{changes:?}");
+        let (at, pressed) = changes[0];
+        assert!(pressed);
+        assert!((time - DT..=time + DT).contains(&at), "{at}");
+        assert!(debounce.pressed());
+    }
+
+    #[test]
+    fn release_clears_after_the_time() {
+        let mut debounce = Debounce::new();
+        hold(&mut debounce, true, 0.05);
+        // A bounce while held does not release it
+        assert!(hold(&mut debounce, false, 0.003).is_empty());
+        assert!(hold(&mut debounce, true, 0.003).is_empty());
+        let changes = hold(&mut debounce, false, 0.05);
+        let time = DEBOUNCE_TIME.as_secs_f32();
+        assert_eq!(changes.len(), 1, "{changes:?}");
+        let (at, pressed) = changes[0];
+        assert!(!pressed);
+        assert!((time - DT..=time + DT).contains(&at), "{at}");
+        assert!(!debounce.pressed());
+    }
+
+    #[test]
+    fn zero_time_follows_the_raw_level() {
+        let mut debounce = Debounce::new();
+        assert_eq!(debounce.update(true, DT, Duration::ZERO), Some(true));
+        assert_eq!(debounce.update(false, DT, Duration::ZERO), Some(false));
+    }
+}
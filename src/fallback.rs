@@ -0,0 +1,242 @@
+//! Steering from other inputs while the steering sensor is out.
+
+/// Time for the key axis to go from centre to full lock, in seconds.
+const KEY_RAMP_TIME: f32 = 0.75;
+/// Time for the key axis to return from full lock to centre, in seconds,
+/// also used when steering back across the centre.
+const KEY_RETURN_TIME: f32 = 0.3;
+/// How long the sensor has to give readings again before it steers, in
+/// seconds, so that a flaky sensor does not make the steering jump around.
+const RESTORE_TIME: f32 = 1.0;
+
+/// What steers while the steering sensor is out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackKind {
+    /// Nothing, the steering holds as the profile says.
+    None,
+    /// The joystick X axis.
+    Joystick,
+    /// Two keypad keys, ramping a virtual axis.
+    Keys,
+}
+
+impl FallbackKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(FallbackKind::None),
+            1 => Some(FallbackKind::Joystick),
+            2 => Some(FallbackKind::Keys),
+            _ => None,
+        }
+    }
+}
+
+/// A steering axis ramped by a left and a right key, like steering with
+/// a keyboard in a game.
+#[derive(Debug, Clone, Default)]
+pub struct KeyAxis {
+    value: f32,
+}
+
+impl KeyAxis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the key states `dt` seconds after the previous update, and
+    /// returns the axis from -1.0 (full left) to 1.0 (full right).
+    pub fn update(&mut self, left: bool, right: bool, dt: f32) -> f32 {
+        let target = match (left, right) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            _ => 0.0,
+        };
+        // Away from the centre at the ramp rate, back towards it quicker
+        let time = if target != 0.0 && target * self.value >= 0.0 {
+            KEY_RAMP_TIME
+        } else {
+            KEY_RETURN_TIME
+        };
+        let step = dt / time;
+        self.value += (target - self.value).clamp(-step, step);
+        self.value
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
+
+/// The input that steers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Active {
+    Sensor,
+    Fallback,
+}
+
+/// Picks between the steering sensor and the fallback input: the fallback
+/// takes over as soon as the sensor is degraded, and hands back once the
+/// sensor has given readings for a while again.
+#[derive(Debug, Clone)]
+pub struct SourcePolicy {
+    fallback: FallbackKind,
+    active: Active,
+    restoring: f32,
+}
+
+impl SourcePolicy {
+    pub fn new(fallback: FallbackKind) -> Self {
+        Self {
+            fallback,
+            active: Active::Sensor,
+            restoring: 0.0,
+        }
+    }
+
+    pub fn fallback(&self) -> FallbackKind {
+        self.fallback
+    }
+
+    pub fn set_fallback(&mut self, fallback: FallbackKind) {
+        self.fallback = fallback;
+        if fallback == FallbackKind::None {
+            self.active = Active::Sensor;
+        }
+    }
+
+    pub fn active(&self) -> Active {
+        self.active
+    }
+
+    /// Takes whether the sensor gave a reading and whether it is degraded,
+    /// `dt` seconds after the previous update, and returns the input that
+    /// steers now.
+    pub fn update(&mut self, reading: bool, degraded: bool, dt: f32) -> Active {
+        match self.active {
+            Active::Sensor if degraded && self.fallback != FallbackKind::None => {
+                self.active = Active::Fallback;
+                self.restoring = 0.0;
+            }
+            Active::Fallback if reading && !degraded => {
+                self.restoring += dt;
+                if self.restoring >= RESTORE_TIME {
+                    self.active = Active::Sensor;
+                }
+            }
+            Active::Fallback => self.restoring = 0.0,
+            Active::Sensor => {}
+        }
+        self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.0025;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    /// Updates the axis for `time` seconds with the keys held as given.
+    fn hold(axis: &mut KeyAxis, left: bool, right: bool, time: f32) -> f32 {
+        for _ in 0..(time / DT).round() as u32 {
+            axis.update(left, right, DT);
+        }
+        axis.value()
+    }
+
+    /// Updates the policy for `time` seconds with the sensor as given.
+    fn run(policy: &mut SourcePolicy, reading: bool, degraded: bool, time: f32) -> Active {
+        for _ in 0..(time / DT).round() as u32 {
+            policy.update(reading, degraded, DT);
+        }
+        policy.active()
+    }
+
+    #[test]
+    fn fails_over_at_once_when_degraded() {
+        let mut policy = SourcePolicy::new(FallbackKind::Keys);
+        assert_eq!(run(&mut policy, true, false, 1.0), Active::Sensor);
+        assert_eq!(policy.update(false, true, DT), Active::Fallback);
+    }
+
+    #[test]
+    fn restores_after_readings_for_a_while() {
+        let mut policy = SourcePolicy::new(FallbackKind::Joystick);
+        run(&mut policy, false, true, 0.5);
+        assert_eq!(
+            run(&mut policy, true, false, RESTORE_TIME - 0.1),
+            Active::Fallback
+        );
+        assert_eq!(run(&mut policy, true, false, 0.2), Active::Sensor);
+    }
+
+    #[test]
+    fn dropout_restarts_the_restore_delay() {
+        let mut policy = SourcePolicy::new(FallbackKind::Joystick);
+        run(&mut policy, false, true, 0.5);
+        run(&mut policy, true, false, RESTORE_TIME * 0.8);
+        // A single missed reading, and the full delay starts over
+        policy.update(false, false, DT);
+        assert_eq!(
+            run(&mut policy, true, false, RESTORE_TIME * 0.8),
+            Active::Fallback
+        );
+        // Likewise when it turns degraded again while still readable
+        policy.update(true, true, DT);
+        assert_eq!(
+            run(&mut policy, true, false, RESTORE_TIME * 0.8),
+            Active::Fallback
+        );
+        assert_eq!(
+            run(&mut policy, true, false, RESTORE_TIME * 0.3),
+            Active::Sensor
+        );
+    }
+
+    #[test]
+    fn no_fallback_keeps_the_sensor() {
+        let mut policy = SourcePolicy::new(FallbackKind::None);
+        assert_eq!(run(&mut policy, false, true, 1.0), Active::Sensor);
+
+        let mut policy = SourcePolicy::new(FallbackKind::Keys);
+        run(&mut policy, false, true, 0.5);
+        assert_eq!(policy.active(), Active::Fallback);
+        policy.set_fallback(FallbackKind::None);
+        assert_eq!(policy.active(), Active::Sensor);
+        assert_eq!(policy.fallback(), FallbackKind::None);
+        assert_eq!(run(&mut policy, false, true, 1.0), Active::Sensor);
+    }
+
+    #[test]
+    fn key_axis_ramps_out_and_returns_quicker() {
+        let mut axis = KeyAxis::new();
+        assert!(close(
+            hold(&mut axis, false, true, KEY_RAMP_TIME / 2.0),
+            0.5
+        ));
+        assert!(close(hold(&mut axis, false, true, KEY_RAMP_TIME), 1.0));
+        // Released, back to the centre at the return rate
+        assert!(close(
+            hold(&mut axis, false, false, KEY_RETURN_TIME / 2.0),
+            0.5
+        ));
+        assert!(close(hold(&mut axis, false, false, KEY_RETURN_TIME), 0.0));
+        // Both keys cancel out
+        assert!(close(hold(&mut axis, true, true, 1.0), 0.0));
+        assert!(close(hold(&mut axis, true, false, KEY_RAMP_TIME), -1.0));
+    }
+
+    #[test]
+    fn key_axis_crosses_the_centre_quickly() {
+        let mut axis = KeyAxis::new();
+        hold(&mut axis, false, true, KEY_RAMP_TIME);
+        // Back to the centre at the return rate, then out at the ramp rate
+        assert!(close(hold(&mut axis, true, false, KEY_RETURN_TIME), 0.0));
+        let time = KEY_RAMP_TIME / 4.0;
+        assert!(close(hold(&mut axis, true, false, time), -0.25));
+    }
+}
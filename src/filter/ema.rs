@@ -0,0 +1,40 @@
+use super::Filter;
+use std::f32::consts::PI;
+
+/// First-order low-pass filter (exponential moving average).
+#[derive(Debug, Clone)]
+pub struct Ema {
+    cutoff: f32,
+    value: Option<f32>,
+}
+
+impl Ema {
+    /// Creates a filter with the given cutoff frequency in Hz.
+    pub fn new(cutoff: f32) -> Self {
+        Self {
+            cutoff,
+            value: None,
+        }
+    }
+}
+
+/// Smoothing factor of a first-order low-pass filter.
+pub(crate) fn alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * PI * cutoff);
+    dt / (dt + tau)
+}
+
+impl Filter for Ema {
+    fn update(&mut self, x: f32, dt: f32) -> f32 {
+        let y = match self.value {
+            Some(prev) => prev + alpha(self.cutoff, dt) * (x - prev),
+            None => x,
+        };
+        self.value = Some(y);
+        y
+    }
+
+    fn reset(&mut self) {
+        self.value = None;
+    }
+}
@@ -0,0 +1,316 @@
+//! Signal conditioning for the report axes.
+
+mod ema;
+pub use ema::Ema;
+
+mod one_euro;
+pub use one_euro::OneEuro;
+
+mod slew;
+pub use slew::SlewLimiter;
+
+/// Maximum number of stages in a [`Chain`].
+pub const MAX_STAGES: usize = 4;
+
+pub trait Filter {
+    /// Filters a new input sampled `dt` seconds after the previous one.
+    fn update(&mut self, x: f32, dt: f32) -> f32;
+
+    /// Forgets the previous inputs, so the next one passes unchanged.
+    fn reset(&mut self);
+}
+
+/// Settings of a single filter stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StageConfig {
+    OneEuro { min_cutoff: f32, beta: f32 },
+    Ema { cutoff: f32 },
+    Slew { max_rate: f32 },
+}
+
+impl StageConfig {
+    /// Whether the parameters make a working filter: cutoffs and rates
+    /// finite and above zero, and the One-Euro speed coefficient finite
+    /// and not negative.
+    pub fn valid(&self) -> bool {
+        let positive = |x: f32| x.is_finite() && x > 0.0;
+        match *self {
+            StageConfig::OneEuro { min_cutoff, beta } => {
+                positive(min_cutoff) && beta.is_finite() && beta >= 0.0
+            }
+            StageConfig::Ema { cutoff } => positive(cutoff),
+            StageConfig::Slew { max_rate } => positive(max_rate),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Stage {
+    OneEuro(OneEuro),
+    Ema(Ema),
+    Slew(SlewLimiter),
+}
+
+impl Stage {
+    fn new(config: StageConfig) -> Self {
+        match config {
+            StageConfig::OneEuro { min_cutoff, beta } => {
+                Stage::OneEuro(OneEuro::new(min_cutoff, beta))
+            }
+            StageConfig::Ema { cutoff } => Stage::Ema(Ema::new(cutoff)),
+            StageConfig::Slew { max_rate } => Stage::Slew(SlewLimiter::new(max_rate)),
+        }
+    }
+
+    fn filter(&mut self) -> &mut dyn Filter {
+        match self {
+            Stage::OneEuro(f) => f,
+            Stage::Ema(f) => f,
+            Stage::Slew(f) => f,
+        }
+    }
+}
+
+/// Filter stages applied one after the other to a single axis.
+///
+/// An empty chain passes its input through unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct Chain {
+    configs: Vec<StageConfig>,
+    stages: Vec<Stage>,
+}
+
+impl Chain {
+    pub fn new(configs: &[StageConfig]) -> Self {
+        let mut res = Self::default();
+        res.configure(configs);
+        res
+    }
+
+    /// Replaces all stages. Stages beyond [`MAX_STAGES`] are ignored.
+    pub fn configure(&mut self, configs: &[StageConfig]) {
+        let configs = &configs[..configs.len().min(MAX_STAGES)];
+        self.configs = configs.to_vec();
+        self.stages = configs.iter().copied().map(Stage::new).collect();
+    }
+
+    /// Sets the stage at `index`, appending it if `index` is the current
+    /// length, or removes it and all following stages if `config` is `None`.
+    ///
+    /// Returns `false` if `index` is out of range or the config is not
+    /// [`StageConfig::valid`].
+    pub fn set_stage(&mut self, index: usize, config: Option<StageConfig>) -> bool {
+        let mut configs = self.configs.clone();
+        match config {
+            Some(config) if !config.valid() => return false,
+            Some(config) if index < configs.len() => configs[index] = config,
+            Some(config) if index == configs.len() && index < MAX_STAGES => configs.push(config),
+            None if index <= configs.len() => configs.truncate(index),
+            _ => return false,
+        }
+        self.configure(&configs);
+        true
+    }
+
+    pub fn configs(&self) -> &[StageConfig] {
+        &self.configs
+    }
+}
+
+impl Filter for Chain {
+    fn update(&mut self, x: f32, dt: f32) -> f32 {
+        self.stages
+            .iter_mut()
+            .fold(x, |x, stage| stage.filter().update(x, dt))
+    }
+
+    fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.filter().reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Synthetic traces, not captures from a sensor: a known steering angle
+    // with hand tremor and sensor noise added
+    const TREMOR_CENTRE: &str = include_str!("traces/tremor_centre.csv");
+    const SLOW_TURN: &str = include_str!("traces/slow_turn.csv");
+    const QUICK_FLICK: &str = include_str!("traces/quick_flick.csv");
+
+    /// Parses a trace into (time, angle) pairs.
+    fn load(csv: &str) -> Vec<(f32, f32)> {
+        csv.lines()
+            .filter(|line| !line.starts_with('#'))
+            .skip(1)
+            .map(|line| {
+                let (t, x) = line.split_once(',').unwrap();
+                (t.parse().unwrap(), x.parse().unwrap())
+            })
+            .collect()
+    }
+
+    fn run(filter: &mut impl Filter, trace: &[(f32, f32)]) -> Vec<f32> {
+        let mut prev_t = trace[0].0;
+        trace
+            .iter()
+            .map(|&(t, x)| {
+                let y = filter.update(x, t - prev_t);
+                prev_t = t;
+                y
+            })
+            .collect()
+    }
+
+    /// RMS of the change between consecutive values.
+    fn jitter(values: &[f32]) -> f32 {
+        let sum: f32 = values.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+        (sum / (values.len() - 1) as f32).sqrt()
+    }
+
+    /// Time at which the values first reach `level`.
+    fn time_to_reach(trace: &[(f32, f32)], values: &[f32], level: f32) -> f32 {
+        let index = values.iter().position(|&y| y >= level).unwrap();
+        trace[index].0
+    }
+
+    fn steering_one_euro() -> OneEuro {
+        OneEuro::new(1.0, 0.1)
+    }
+
+    #[test]
+    fn one_euro_removes_tremor_at_rest() {
+        let trace = load(TREMOR_CENTRE);
+        let input: Vec<f32> = trace.iter().map(|&(_, x)| x).collect();
+        let output = run(&mut steering_one_euro(), &trace);
+        assert!(jitter(&output[50..]) < jitter(&input[50..]) / 4.0);
+    }
+
+    #[test]
+    fn one_euro_follows_slow_turn() {
+        let trace = load(SLOW_TURN);
+        let output = run(&mut steering_one_euro(), &trace);
+        let truth = |t: f32| {
+            let u = ((t - 0.5) / 3.0).clamp(0.0, 1.0);
+            90.0 * (0.5 - 0.5 * (std::f32::consts::PI * u).cos())
+        };
+        for (&(t, _), &y) in trace.iter().zip(output.iter()).skip(50) {
+            assert!((y - truth(t)).abs() < 3.0, "t = {t}: {y} vs {}", truth(t));
+        }
+    }
+
+    #[test]
+    fn one_euro_keeps_up_with_quick_flick() {
+        let trace = load(QUICK_FLICK);
+        let input: Vec<f32> = trace.iter().map(|&(_, x)| x).collect();
+        let output = run(&mut steering_one_euro(), &trace);
+        let lag = time_to_reach(&trace, &output, 108.0) - time_to_reach(&trace, &input, 108.0);
+        assert!(lag < 0.06, "lag {lag} s");
+
+        // An EMA smoothing as much at rest lags far behind
+        let output = run(&mut Ema::new(1.0), &trace);
+        let lag = time_to_reach(&trace, &output, 108.0) - time_to_reach(&trace, &input, 108.0);
+        assert!(lag > 0.2, "lag {lag} s");
+    }
+
+    #[test]
+    fn ema_reduces_tremor() {
+        let trace = load(TREMOR_CENTRE);
+        let input: Vec<f32> = trace.iter().map(|&(_, x)| x).collect();
+        let output = run(&mut Ema::new(2.0), &trace);
+        assert!(jitter(&output[50..]) < jitter(&input[50..]) / 3.0);
+    }
+
+    #[test]
+    fn ema_step_response() {
+        let mut ema = Ema::new(1.0);
+        ema.update(0.0, 0.0);
+        let tau = 1.0 / (2.0 * std::f32::consts::PI);
+        let steps = 1000;
+        let mut y = 0.0;
+        for _ in 0..steps {
+            y = ema.update(1.0, tau / steps as f32);
+        }
+        // One time constant reaches 1 - 1/e
+        assert!((y - (1.0 - (-1.0f32).exp())).abs() < 0.01, "{y}");
+    }
+
+    #[test]
+    fn slew_limits_rate() {
+        let trace = load(QUICK_FLICK);
+        let output = run(&mut SlewLimiter::new(240.0), &trace);
+        for (w, t) in output.windows(2).zip(trace.windows(2)) {
+            let rate = (w[1] - w[0]).abs() / (t[1].0 - t[0].0);
+            assert!(rate <= 240.0 * 1.001, "{rate}");
+        }
+        // Half the speed of the flick, so it falls behind but catches up during the hold
+        assert!(output[70] < 80.0);
+        assert!((output[150] - trace[150].1).abs() < 1.0);
+    }
+
+    #[test]
+    fn empty_chain_passes_through() {
+        let trace = load(QUICK_FLICK);
+        let output = run(&mut Chain::default(), &trace);
+        for (&(_, x), &y) in trace.iter().zip(output.iter()) {
+            assert_eq!(x, y);
+        }
+    }
+
+    #[test]
+    fn chain_applies_stages_in_order() {
+        let trace = load(QUICK_FLICK);
+        let stages = [
+            StageConfig::OneEuro {
+                min_cutoff: 1.0,
+                beta: 0.1,
+            },
+            StageConfig::Slew { max_rate: 240.0 },
+        ];
+        let mut chain = Chain::new(&stages);
+        let expected = {
+            let smoothed = run(&mut steering_one_euro(), &trace);
+            let trace: Vec<(f32, f32)> = trace.iter().map(|&(t, _)| t).zip(smoothed).collect();
+            run(&mut SlewLimiter::new(240.0), &trace)
+        };
+        assert_eq!(run(&mut chain, &trace), expected);
+    }
+
+    #[test]
+    fn chain_set_stage() {
+        let ema = StageConfig::Ema { cutoff: 2.0 };
+        let slew = StageConfig::Slew { max_rate: 100.0 };
+        let mut chain = Chain::default();
+        assert!(!chain.set_stage(1, Some(ema)));
+        assert!(chain.set_stage(0, Some(ema)));
+        assert!(chain.set_stage(1, Some(slew)));
+        assert!(chain.set_stage(0, Some(slew)));
+        assert_eq!(chain.configs(), &[slew, slew]);
+        assert!(chain.set_stage(1, None));
+        assert_eq!(chain.configs(), &[slew]);
+        for _ in 1..MAX_STAGES {
+            let len = chain.configs().len();
+            assert!(chain.set_stage(len, Some(ema)));
+        }
+        assert!(!chain.set_stage(MAX_STAGES, Some(ema)));
+    }
+
+    #[test]
+    fn chain_rejects_broken_stages() {
+        let mut chain = Chain::default();
+        for max_rate in [-1.0, 0.0, f32::NAN, f32::INFINITY] {
+            assert!(!chain.set_stage(0, Some(StageConfig::Slew { max_rate })));
+        }
+        let one_euro = StageConfig::OneEuro {
+            min_cutoff: 1.0,
+            beta: -0.1,
+        };
+        assert!(!chain.set_stage(0, Some(one_euro)));
+        assert!(chain.configs().is_empty());
+        // Still filters without panicking
+        assert_eq!(chain.update(5.0, 0.01), 5.0);
+    }
+}
@@ -0,0 +1,51 @@
+use super::{ema::alpha, Filter};
+
+/// One-Euro filter: a low-pass filter whose cutoff rises with speed,
+/// removing jitter at rest while keeping lag low during fast movements.
+///
+/// See <https://gery.casiez.net/1euro/>.
+#[derive(Debug, Clone)]
+pub struct OneEuro {
+    min_cutoff: f32,
+    beta: f32,
+    d_cutoff: f32,
+    value: Option<f32>,
+    derivative: f32,
+}
+
+impl OneEuro {
+    /// Creates a filter with the cutoff in Hz used at rest, and how much
+    /// the cutoff rises per unit/s of speed.
+    pub fn new(min_cutoff: f32, beta: f32) -> Self {
+        Self {
+            min_cutoff,
+            beta,
+            d_cutoff: 1.0,
+            value: None,
+            derivative: 0.0,
+        }
+    }
+}
+
+impl Filter for OneEuro {
+    fn update(&mut self, x: f32, dt: f32) -> f32 {
+        let prev = match self.value {
+            Some(prev) if dt > 0.0 => prev,
+            _ => {
+                self.value = Some(x);
+                return x;
+            }
+        };
+        let derivative = (x - prev) / dt;
+        self.derivative += alpha(self.d_cutoff, dt) * (derivative - self.derivative);
+        let cutoff = self.min_cutoff + self.beta * self.derivative.abs();
+        let y = prev + alpha(cutoff, dt) * (x - prev);
+        self.value = Some(y);
+        y
+    }
+
+    fn reset(&mut self) {
+        self.value = None;
+        self.derivative = 0.0;
+    }
+}
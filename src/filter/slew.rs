@@ -0,0 +1,36 @@
+use super::Filter;
+
+/// Limits how fast the output may change.
+#[derive(Debug, Clone)]
+pub struct SlewLimiter {
+    max_rate: f32,
+    value: Option<f32>,
+}
+
+impl SlewLimiter {
+    /// Creates a limiter with the maximum rate of change in units/s.
+    pub fn new(max_rate: f32) -> Self {
+        Self {
+            max_rate,
+            value: None,
+        }
+    }
+}
+
+impl Filter for SlewLimiter {
+    fn update(&mut self, x: f32, dt: f32) -> f32 {
+        let y = match self.value {
+            Some(prev) => {
+                let step = self.max_rate * dt;
+                prev + (x - prev).clamp(-step, step)
+            }
+            None => x,
+        };
+        self.value = Some(y);
+        y
+    }
+
+    fn reset(&mut self) {
+        self.value = None;
+    }
+}
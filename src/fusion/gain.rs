@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+/// Gain used right after boot, to converge quickly from the identity start.
+pub const BOOT_BETA: f32 = 2.5;
+
+/// How far |a| may stray from 1 g before the accelerometer loses trust.
+const ACCEL_TOLERANCE: f32 = 0.05;
+/// Deviation from 1 g at which the accelerometer is ignored.
+const ACCEL_REJECT: f32 = 0.3;
+/// Gyro rate (rad/s) up to which the accelerometer keeps full trust.
+const RATE_TOLERANCE: f32 = 1.0;
+/// Gyro rate (rad/s) at which the accelerometer is ignored.
+const RATE_REJECT: f32 = 6.0;
+
+/// Madgwick `beta` that backs off when the accelerometer measures more than
+/// gravity, so bumps and shaking do not pull the orientation around.
+#[derive(Debug, Clone)]
+pub struct AdaptiveGain {
+    beta: f32,
+    boot_time: Duration,
+    boot_left: f32,
+}
+
+impl AdaptiveGain {
+    /// `beta` is the gain with full accelerometer trust, used once
+    /// `boot_time` of samples has passed at [`BOOT_BETA`].
+    pub fn new(beta: f32, boot_time: Duration) -> Self {
+        Self {
+            beta,
+            boot_time,
+            boot_left: boot_time.as_secs_f32(),
+        }
+    }
+
+    /// Goes back to the boot gain, e.g. after the sensor was re-initialized.
+    pub fn restart(&mut self) {
+        self.boot_left = self.boot_time.as_secs_f32();
+    }
+
+    /// Whether the boot gain is still in use.
+    pub fn converging(&self) -> bool {
+        self.boot_left > 0.0
+    }
+
+    /// Gain for a sample of `accel` (g) and `gyro` (rad/s) taken `dt`
+    /// seconds after the previous one.
+    pub fn beta(&mut self, accel: [f32; 3], gyro: [f32; 3], dt: f32) -> f32 {
+        let beta = if self.converging() {
+            self.boot_left -= dt;
+            BOOT_BETA
+        } else {
+            self.beta
+        };
+        beta * accel_trust(accel, gyro)
+    }
+}
+
+/// How much the accelerometer can be taken as gravity, from 0.0 to 1.0.
+pub fn accel_trust(accel: [f32; 3], gyro: [f32; 3]) -> f32 {
+    let norm = |v: [f32; 3]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let deviation = (norm(accel) - 1.0).abs();
+    ramp_down(deviation, ACCEL_TOLERANCE, ACCEL_REJECT)
+        * ramp_down(norm(gyro), RATE_TOLERANCE, RATE_REJECT)
+}
+
+/// 1.0 up to `start`, falling linearly to 0.0 at `end`.
+fn ramp_down(x: f32, start: f32, end: f32) -> f32 {
+    (1.0 - (x - start) / (end - start)).clamp(0.0, 1.0)
+}
@@ -0,0 +1,301 @@
+/// Temperature the model offset refers to, in °C.
+const REFERENCE_TEMP: f32 = 25.0;
+/// Length of the blocks rest samples are averaged over, in seconds.
+const BLOCK_TIME: f32 = 1.0;
+/// Largest rate (rad/s) away from the expected bias still taken as rest,
+/// about 1.7 dps. A slow steady turn is not told apart from bias by the gyro
+/// alone, so the rate is measured from the bias the model and the fusion
+/// filter already expect, not from zero, or an untrimmed unit would never
+/// learn.
+const REST_RATE: f32 = 0.03;
+/// Largest spread (rad/s) of the rate within a block still taken as rest.
+const REST_SPREAD: f32 = 0.03;
+/// Largest change (g) of the mean acceleration between the two halves of a
+/// block still taken as rest. Gravity turning by half a block of a 0.01 rad/s
+/// turn about a level axis already moves it this much.
+const REST_TILT: f32 = 0.005;
+/// Largest departure of the acceleration norm from 1 g still taken as rest.
+const REST_NORM: f32 = 0.05;
+/// Weight older blocks keep each time a new one is added.
+const FORGET: f32 = 0.995;
+/// Spread (standard deviation, °C) of the block temperatures needed before
+/// the slope is fitted rather than kept.
+const MIN_TEMP_SPREAD: f32 = 1.0;
+
+/// Gyro bias as a linear function of the die temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GyroTempModel {
+    /// Bias at 25 °C, in rad/s.
+    pub offset: [f32; 3],
+    /// Bias change per °C, in rad/s.
+    pub slope: [f32; 3],
+}
+
+impl GyroTempModel {
+    pub fn bias(&self, temp: f32) -> [f32; 3] {
+        [0, 1, 2].map(|i| self.offset[i] + self.slope[i] * (temp - REFERENCE_TEMP))
+    }
+
+    /// Removes the modelled bias from a gyro reading.
+    pub fn compensate(&self, gyro: [f32; 3], temp: f32) -> [f32; 3] {
+        let bias = self.bias(temp);
+        [0, 1, 2].map(|i| gyro[i] - bias[i])
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Block {
+    time: f32,
+    count: u32,
+    temp: f32,
+    gyro: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+    /// Sums of the acceleration over the first and second half of the block.
+    accel: [[f32; 3]; 2],
+    accel_count: [u32; 2],
+}
+
+/// Learns a [`GyroTempModel`] from the raw gyro while the wheel is at rest,
+/// which takes both a small steady rate and gravity staying put.
+///
+/// Rest samples are averaged in blocks of a second, and the blocks fitted
+/// by least squares, with older blocks slowly forgotten. Until the blocks
+/// span enough temperature, only the offset is learned and the slope kept.
+#[derive(Debug, Clone)]
+pub struct GyroTempLearner {
+    model: GyroTempModel,
+    block: Block,
+    w: f32,
+    wt: f32,
+    wtt: f32,
+    wb: [f32; 3],
+    wtb: [f32; 3],
+}
+
+impl GyroTempLearner {
+    /// Starts from a previously learned (or default) model.
+    pub fn new(model: GyroTempModel) -> Self {
+        Self {
+            model,
+            block: Block::default(),
+            w: 0.0,
+            wt: 0.0,
+            wtt: 0.0,
+            wb: [0.0; 3],
+            wtb: [0.0; 3],
+        }
+    }
+
+    pub fn model(&self) -> &GyroTempModel {
+        &self.model
+    }
+
+    /// Feeds a raw sample of `accel` (g) and `gyro` (rad/s) at die
+    /// temperature `temp` (°C), taken `dt` seconds after the previous one.
+    /// `drift` is the bias the model still misses, as the fusion filter
+    /// estimates it in the sensor frame.
+    ///
+    /// Returns true when the model was updated.
+    pub fn update(
+        &mut self,
+        accel: [f32; 3],
+        gyro: [f32; 3],
+        temp: f32,
+        drift: [f32; 3],
+        dt: f32,
+    ) -> bool {
+        let bias = self.model.bias(temp);
+        let turning = (0..3).any(|i| (gyro[i] - bias[i] - drift[i]).abs() > REST_RATE);
+        let norm = accel.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if turning || (norm - 1.0).abs() > REST_NORM {
+            self.block = Block::default();
+            return false;
+        }
+
+        let block = &mut self.block;
+        if block.count == 0 {
+            block.min = gyro;
+            block.max = gyro;
+        }
+        let half = usize::from(block.time >= BLOCK_TIME / 2.0);
+        for (sum, x) in block.accel[half].iter_mut().zip(accel) {
+            *sum += x;
+        }
+        block.accel_count[half] += 1;
+        block.time += dt;
+        block.count += 1;
+        block.temp += temp;
+        for (i, x) in gyro.into_iter().enumerate() {
+            block.gyro[i] += x;
+            block.min[i] = block.min[i].min(x);
+            block.max[i] = block.max[i].max(x);
+        }
+        if block.time < BLOCK_TIME {
+            return false;
+        }
+
+        let block = std::mem::take(&mut self.block);
+        if (0..3).any(|i| block.max[i] - block.min[i] > REST_SPREAD) {
+            return false;
+        }
+        let [first, second] = [0, 1].map(|h| {
+            let n = block.accel_count[h].max(1) as f32;
+            block.accel[h].map(|x| x / n)
+        });
+        if (0..3).any(|i| (second[i] - first[i]).abs() > REST_TILT) {
+            return false;
+        }
+        let n = block.count as f32;
+        self.add(block.temp / n, block.gyro.map(|x| x / n));
+        true
+    }
+
+    fn add(&mut self, temp: f32, bias: [f32; 3]) {
+        // Relative to the reference, to keep the sums small
+        let t = temp - REFERENCE_TEMP;
+        self.w = self.w * FORGET + 1.0;
+        self.wt = self.wt * FORGET + t;
+        self.wtt = self.wtt * FORGET + t * t;
+        for (i, b) in bias.into_iter().enumerate() {
+            self.wb[i] = self.wb[i] * FORGET + b;
+            self.wtb[i] = self.wtb[i] * FORGET + t * b;
+        }
+
+        let mean_t = self.wt / self.w;
+        let var_t = self.wtt / self.w - mean_t * mean_t;
+        for i in 0..3 {
+            let mean_b = self.wb[i] / self.w;
+            if var_t >= MIN_TEMP_SPREAD * MIN_TEMP_SPREAD {
+                self.model.slope[i] = (self.wtb[i] / self.w - mean_t * mean_b) / var_t;
+            }
+            self.model.offset[i] = mean_b - self.model.slope[i] * mean_t;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    /// Feeds `time` seconds of samples, with the accel and gyro of each
+    /// given by a function of the time since the start.
+    fn feed(
+        learner: &mut GyroTempLearner,
+        time: f32,
+        temp: f32,
+        sample: impl Fn(f32) -> ([f32; 3], [f32; 3]),
+    ) -> u32 {
+        feed_with_drift(learner, time, temp, [0.0; 3], sample)
+    }
+
+    fn feed_with_drift(
+        learner: &mut GyroTempLearner,
+        time: f32,
+        temp: f32,
+        drift: [f32; 3],
+        sample: impl Fn(f32) -> ([f32; 3], [f32; 3]),
+    ) -> u32 {
+        let mut updates = 0;
+        for i in 0..(time / DT) as u32 {
+            let (accel, gyro) = sample(i as f32 * DT);
+            if learner.update(accel, gyro, temp, drift, DT) {
+                updates += 1;
+            }
+        }
+        updates
+    }
+
+    /// A little noise that averages out over a block.
+    fn jitter(t: f32) -> f32 {
+        (t * 1234.5).sin() * 0.002
+    }
+
+    #[test]
+    fn learns_offset_at_rest() {
+        let mut learner = GyroTempLearner::new(GyroTempModel::default());
+        let bias = [0.01, -0.02, 0.005];
+        let updates = feed(&mut learner, 5.0, 30.0, |t| {
+            ([0.0, 0.0, 1.0], bias.map(|b| b + jitter(t)))
+        });
+        assert!(updates >= 4, "{updates}");
+        let learned = learner.model().bias(30.0);
+        for i in 0..3 {
+            assert!((learned[i] - bias[i]).abs() < 1e-3, "{learned:?}");
+        }
+    }
+
+    #[test]
+    fn fits_slope_over_temperature_sweep() {
+        let mut learner = GyroTempLearner::new(GyroTempModel::default());
+        let (offset, slope) = (0.004, 0.0008);
+        for step in 0..20 {
+            let temp = 20.0 + step as f32;
+            let bias = offset + slope * (temp - REFERENCE_TEMP);
+            feed(&mut learner, 2.0, temp, |t| {
+                ([0.0, 0.0, 1.0], [bias + jitter(t), 0.0, -bias])
+            });
+        }
+        let model = learner.model();
+        assert!((model.offset[0] - offset).abs() < 2e-4, "{model:?}");
+        assert!((model.slope[0] - slope).abs() < 2e-5, "{model:?}");
+        assert!((model.slope[2] + slope).abs() < 2e-5, "{model:?}");
+        assert!(model.slope[1].abs() < 1e-6, "{model:?}");
+    }
+
+    #[test]
+    fn rejects_turn_above_rest_rate() {
+        let mut learner = GyroTempLearner::new(GyroTempModel::default());
+        let updates = feed(&mut learner, 5.0, 30.0, |_| {
+            ([0.0, 0.0, 1.0], [0.1, 0.0, 0.0])
+        });
+        assert_eq!(updates, 0);
+        assert_eq!(*learner.model(), GyroTempModel::default());
+    }
+
+    #[test]
+    fn learns_large_bias_from_fusion_estimate() {
+        let mut learner = GyroTempLearner::new(GyroTempModel::default());
+        let bias = [0.08, -0.05, 0.0];
+        // Untrimmed and above the rest rate, so unknown to the model
+        let still = |t| ([0.0, 0.0, 1.0], bias.map(|b| b + jitter(t)));
+        assert_eq!(feed(&mut learner, 3.0, 30.0, still), 0);
+
+        // Once the fusion filter has estimated most of it
+        let drift = [0.07, -0.045, 0.0];
+        assert_eq!(feed_with_drift(&mut learner, 1.5, 30.0, drift, still), 1);
+        // The model has taken it over, so the filter estimate is dropped
+        assert!(feed(&mut learner, 3.0, 30.0, still) >= 2);
+        let learned = learner.model().bias(30.0);
+        for i in 0..3 {
+            assert!((learned[i] - bias[i]).abs() < 1e-3, "{learned:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_slow_steady_turn() {
+        let mut learner = GyroTempLearner::new(GyroTempModel::default());
+        // Below the rest rate, but gravity turns with the wheel
+        let rate = 0.02;
+        let updates = feed(&mut learner, 5.0, 30.0, |t| {
+            let angle = rate * t;
+            (
+                [0.0, -angle.sin(), angle.cos()],
+                [rate + jitter(t), 0.0, 0.0],
+            )
+        });
+        assert_eq!(updates, 0);
+        assert_eq!(*learner.model(), GyroTempModel::default());
+    }
+
+    #[test]
+    fn rejects_shaking() {
+        let mut learner = GyroTempLearner::new(GyroTempModel::default());
+        let updates = feed(&mut learner, 5.0, 30.0, |t| {
+            ([0.0, 0.0, 1.0 + (t * 40.0).sin() * 0.3], [0.0; 3])
+        });
+        assert_eq!(updates, 0);
+    }
+}
@@ -0,0 +1,43 @@
+/// Smallest range (in LSB) of both horizontal axes before headings are trusted.
+const MIN_RANGE: f32 = 150.0;
+
+/// Hard and soft iron calibration from the extremes seen on each axis.
+///
+/// Turning the sensor through a full circle is enough to calibrate the
+/// horizontal axes, which is all a heading needs.
+#[derive(Debug, Clone)]
+pub struct MagCalibration {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Default for MagCalibration {
+    fn default() -> Self {
+        Self {
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        }
+    }
+}
+
+impl MagCalibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a raw reading into account, and returns it centred and scaled
+    /// to about ±1 per axis, once the horizontal axes have seen enough range.
+    pub fn update(&mut self, raw: [f32; 3]) -> Option<[f32; 3]> {
+        for (i, x) in raw.into_iter().enumerate() {
+            self.min[i] = self.min[i].min(x);
+            self.max[i] = self.max[i].max(x);
+        }
+        if (0..2).any(|i| self.max[i] - self.min[i] < MIN_RANGE) {
+            return None;
+        }
+        Some([0, 1, 2].map(|i| {
+            let half = ((self.max[i] - self.min[i]) / 2.0).max(1.0);
+            (raw[i] - (self.max[i] + self.min[i]) / 2.0) / half
+        }))
+    }
+}
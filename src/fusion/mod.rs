@@ -0,0 +1,347 @@
+//! Orientation and angle math shared by the steering sources.
+
+mod gain;
+pub use gain::*;
+
+mod gyro_temp;
+pub use gyro_temp::*;
+
+mod madgwick;
+pub use madgwick::*;
+
+mod mag;
+pub use mag::*;
+
+mod mode;
+pub use mode::*;
+
+mod mount;
+pub use mount::*;
+
+mod turns;
+pub use turns::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use std::time::Duration;
+
+    // Synthetic traces, not captures from a sensor: the readings an IMU in
+    // the hub would give for a known steering angle, with noise, hand
+    // tremor and gyro bias added
+    const STATIC: &str = include_str!("traces/static.csv");
+    const SLOW_TURN: &str = include_str!("traces/slow_turn.csv");
+    const MULTI_TURN: &str = include_str!("traces/multi_turn.csv");
+    const SHAKING: &str = include_str!("traces/shaking.csv");
+
+    struct Row {
+        time: f32,
+        accel: [f32; 3],
+        gyro: [f32; 3],
+        angle: f32,
+    }
+
+    fn load(csv: &str) -> Vec<Row> {
+        csv.lines()
+            .filter(|line| !line.starts_with('#'))
+            .skip(1)
+            .map(|line| {
+                let values: Vec<f32> = line.split(',').map(|x| x.parse().unwrap()).collect();
+                Row {
+                    time: values[0],
+                    accel: [values[1], values[2], values[3]],
+                    gyro: [values[4], values[5], values[6]],
+                    angle: values[7],
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the fusion the way the IMU source does, and returns the roll
+    /// with full turns counted.
+    fn run(trace: &[Row]) -> Vec<f32> {
+        let mut madgwick = Madgwick::new();
+        let mut gain = AdaptiveGain::new(madgwick.beta(), Duration::from_secs(3));
+        let mut turns = TurnCounter::new();
+        let mut prev_time = trace[0].time;
+        trace
+            .iter()
+            .map(|row| {
+                let dt = row.time - prev_time;
+                prev_time = row.time;
+                madgwick.set_beta(gain.beta(row.accel, row.gyro, dt));
+                madgwick.update(row.accel, row.gyro, dt);
+                turns.update(quaternion_to_roll(madgwick.quaternion()))
+            })
+            .collect()
+    }
+
+    /// Largest error from `from` seconds on.
+    fn max_error(trace: &[Row], output: &[f32], from: f32) -> f32 {
+        trace
+            .iter()
+            .zip(output)
+            .filter(|(row, _)| row.time >= from)
+            .map(|(row, y)| (y - row.angle).abs())
+            .fold(0.0, f32::max)
+    }
+
+    fn mean_between(trace: &[Row], output: &[f32], from: f32, to: f32) -> f32 {
+        let values: Vec<f32> = trace
+            .iter()
+            .zip(output)
+            .filter(|(row, _)| (from..to).contains(&row.time))
+            .map(|(_, &y)| y)
+            .collect();
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+
+    fn roll_quaternion(degrees: f32) -> [f32; 4] {
+        // Roll is positive turning the other way round the X axis
+        let half = -degrees.to_radians() / 2.0;
+        [half.cos(), half.sin(), 0.0, 0.0]
+    }
+
+    #[test]
+    fn converges_quickly_at_boot() {
+        let trace = load(STATIC);
+        let output = run(&trace);
+        let converged = trace
+            .iter()
+            .zip(&output)
+            .position(|(row, y)| (y - row.angle).abs() < 1.0)
+            .unwrap();
+        assert!(trace[converged].time < 1.0, "{} s", trace[converged].time);
+    }
+
+    #[test]
+    fn holds_still_without_drift() {
+        let trace = load(STATIC);
+        let output = run(&trace);
+        // Past the boot gain
+        let error = max_error(&trace, &output, 3.0);
+        assert!(error < 1.0, "{error}");
+        let drift =
+            mean_between(&trace, &output, 8.0, 10.0) - mean_between(&trace, &output, 3.0, 5.0);
+        assert!(drift.abs() < 0.3, "{drift}");
+    }
+
+    #[test]
+    fn follows_slow_turn() {
+        let trace = load(SLOW_TURN);
+        let output = run(&trace);
+        let error = max_error(&trace, &output, 1.0);
+        assert!(error < 2.5, "{error}");
+    }
+
+    #[test]
+    fn counts_turns_past_half_turn() {
+        let trace = load(MULTI_TURN);
+        let output = run(&trace);
+        let error = max_error(&trace, &output, 1.0);
+        assert!(error < 3.0, "{error}");
+        let peak = output.iter().cloned().fold(f32::MIN, f32::max);
+        assert!((peak - 720.0).abs() < 3.0, "{peak}");
+        let end = *output.last().unwrap();
+        assert!((end - 360.0).abs() < 1.0, "{end}");
+    }
+
+    #[test]
+    fn shaking_stays_bounded() {
+        let trace = load(SHAKING);
+        let output = run(&trace);
+        // The shaking passes through 1 g now and then, where it still pulls
+        let error = max_error(&trace, &output, 1.0);
+        assert!(error < 15.0, "{error}");
+        // Back on track soon after it stops
+        let error = max_error(&trace, &output, 4.5);
+        assert!(error < 1.0, "{error}");
+    }
+
+    #[test]
+    fn roll_covers_full_turn() {
+        for degrees in [-179.5, -135.0, -90.0, -1.0, 0.0, 1.0, 90.0, 135.0, 179.5] {
+            let roll = quaternion_to_roll(roll_quaternion(degrees));
+            assert!((roll - degrees).abs() < 1e-3, "{degrees}: {roll}");
+        }
+        // Half a turn either way is the same orientation
+        let roll = quaternion_to_roll(roll_quaternion(180.0)).abs();
+        assert!((roll - 180.0).abs() < 1e-3, "{roll}");
+    }
+
+    #[test]
+    fn gravity_roll_matches_quaternion_roll() {
+        for degrees in [-150.0, -45.0, 0.0, 30.0, 170.0] {
+            let r = f32::to_radians(degrees);
+            let roll = gravity_to_roll([0.0, -r.sin(), r.cos()]);
+            assert!((roll - degrees).abs() < 1e-3, "{degrees}: {roll}");
+        }
+    }
+
+    #[test]
+    fn pitch_is_zero_when_rolled() {
+        let pitch = quaternion_to_pitch(roll_quaternion(60.0));
+        assert!(pitch.abs() < 1e-3, "{pitch}");
+        let half = PI / 12.0;
+        let pitch = quaternion_to_pitch([half.cos(), 0.0, half.sin(), 0.0]);
+        assert!((pitch.abs() - 30.0).abs() < 1e-3, "{pitch}");
+    }
+
+    #[test]
+    fn wraps_angles_to_one_turn() {
+        assert_eq!(wrap_angle(0.0), 0.0);
+        assert_eq!(wrap_angle(180.0), -180.0);
+        assert_eq!(wrap_angle(-180.0), -180.0);
+        assert_eq!(wrap_angle(190.0), -170.0);
+        assert_eq!(wrap_angle(-190.0), 170.0);
+        assert_eq!(wrap_angle(725.0), 5.0);
+    }
+
+    #[test]
+    fn turn_counter_unwraps_both_ways() {
+        let mut turns = TurnCounter::new();
+        let mut last = 0.0;
+        // Two turns right in 10° steps, then back to the start
+        let steps = (0..=72).chain((0..72).rev());
+        for step in steps {
+            let angle = step as f32 * 10.0;
+            last = turns.update(wrap_angle(angle));
+            assert!((last - angle).abs() < 1e-3, "{angle}: {last}");
+        }
+        assert!(last.abs() < 1e-3);
+    }
+
+    #[test]
+    fn turn_counter_recenters_within_one_turn() {
+        let mut turns = TurnCounter::new();
+        for step in 0..=40 {
+            turns.update(wrap_angle(step as f32 * 10.0));
+        }
+        // 400° turned, so straight ahead is 40° wrapped
+        assert!((turns.recenter() - 40.0).abs() < 1e-3);
+        assert!((turns.update(50.0) - 10.0).abs() < 1e-3);
+
+        turns.set_zero(-170.0);
+        let angle = turns.update(175.0);
+        assert!((angle + 15.0).abs() < 1e-3, "{angle}");
+    }
+
+    /// The mount as a matrix, from where it takes the sensor axes.
+    fn mount_matrix(mount: &Mount) -> [[f32; 3]; 3] {
+        let columns = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]].map(|e| mount.rotate(e));
+        [0, 1, 2].map(|i| [0, 1, 2].map(|j| columns[j][i]))
+    }
+
+    /// A wheel-frame vector as the sensor under `mount` reads it.
+    fn to_sensor(mount: &Mount, v: [f32; 3]) -> [f32; 3] {
+        let m = mount_matrix(mount);
+        [0, 1, 2].map(|j| (0..3).map(|i| m[i][j] * v[i]).sum())
+    }
+
+    fn gravity_at_roll(degrees: f32) -> [f32; 3] {
+        let r = degrees.to_radians();
+        [0.0, -r.sin(), r.cos()]
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!((0..3).all(|i| (a[i] - b[i]).abs() < 1e-4), "{a:?} {b:?}");
+    }
+
+    #[test]
+    fn axis_aligned_mounts_are_distinct_rotations() {
+        assert_eq!(Mount::axis_aligned(0), Some(Mount::IDENTITY));
+        let mut seen = Vec::new();
+        for index in 0..AXIS_ALIGNED_MOUNTS {
+            let m = mount_matrix(&Mount::axis_aligned(index).unwrap());
+            for i in 0..3 {
+                for j in 0..3 {
+                    let dot: f32 = (0..3).map(|k| m[i][k] * m[j][k]).sum();
+                    let expected = if i == j { 1.0 } else { 0.0 };
+                    assert!((dot - expected).abs() < 1e-6, "{index}: {m:?}");
+                }
+            }
+            // Right-handed, not mirrored
+            let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+            assert!((det - 1.0).abs() < 1e-6, "{index}: {det}");
+            assert!(!seen.contains(&m), "{index}");
+            seen.push(m);
+        }
+        assert_eq!(Mount::axis_aligned(AXIS_ALIGNED_MOUNTS), None);
+        assert_eq!(Mount::axis_aligned(MOUNT_CAPTURED), None);
+    }
+
+    #[test]
+    fn mount_quaternion_round_trips() {
+        for index in 0..AXIS_ALIGNED_MOUNTS {
+            let mount = Mount::axis_aligned(index).unwrap();
+            let back = Mount::from_quaternion(mount.quaternion()).unwrap();
+            for (a, b) in mount_matrix(&mount).iter().zip(mount_matrix(&back)) {
+                assert_close(*a, b);
+            }
+            let v = [0.3, -0.5, 0.8];
+            assert_close(mount.unrotate(mount.rotate(v)), v);
+        }
+        let half = 0.4f32;
+        let q = [
+            half.cos(),
+            0.3 * half.sin(),
+            -0.5 * half.sin(),
+            0.8124 * half.sin(),
+        ];
+        let norm = q.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let q = q.map(|x| x / norm);
+        let stored = Mount::from_quaternion(q).unwrap().quaternion();
+        assert!(
+            (0..4).all(|i| (stored[i] - q[i]).abs() < 1e-4),
+            "{q:?} {stored:?}"
+        );
+        // The same rotation with w negative is stored the same way
+        let stored = Mount::from_quaternion(q.map(|x| -x)).unwrap().quaternion();
+        assert!(
+            (0..4).all(|i| (stored[i] - q[i]).abs() < 1e-4),
+            "{q:?} {stored:?}"
+        );
+
+        assert_eq!(Mount::from_quaternion([0.0; 4]), None);
+        assert_eq!(Mount::from_quaternion([f32::NAN, 0.0, 0.0, 1.0]), None);
+    }
+
+    #[test]
+    fn mount_calibration_finds_the_column() {
+        for index in [0, 5, 13, 22] {
+            let actual = Mount::axis_aligned(index).unwrap();
+            for turn in [30.0, -45.0] {
+                let mut calibration = MountCalibration::new();
+                let centre = to_sensor(&actual, gravity_at_roll(0.0));
+                assert_eq!(calibration.capture(centre), MountCapture::Centre);
+                let turned = to_sensor(&actual, gravity_at_roll(turn));
+                let MountCapture::Done(mount) = calibration.capture(turned) else {
+                    panic!("{index} {turn}");
+                };
+                assert_close(mount.rotate(centre), [0.0, 0.0, 1.0]);
+                // Roll grows in the direction the wheel was turned
+                let roll = gravity_to_roll(mount.rotate(turned));
+                assert!((roll - turn.abs()).abs() < 1e-3, "{index} {turn}: {roll}");
+            }
+        }
+    }
+
+    #[test]
+    fn mount_calibration_needs_a_turn() {
+        let mut calibration = MountCalibration::new();
+        calibration.capture(gravity_at_roll(0.0));
+        assert_eq!(
+            calibration.capture(gravity_at_roll(10.0)),
+            MountCapture::TurnTooSmall
+        );
+        // Starts over from the centre
+        assert_eq!(
+            calibration.capture(gravity_at_roll(0.0)),
+            MountCapture::Centre
+        );
+        assert_eq!(calibration.capture([0.0; 3]), MountCapture::TurnTooSmall);
+    }
+}
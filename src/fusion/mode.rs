@@ -0,0 +1,26 @@
+/// Which rotation of the IMU steers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxisMode {
+    /// Around the wheel X axis, for a board in the wheel hub.
+    Roll,
+    /// Around the wheel Y axis, e.g. for a phone-style grip.
+    Pitch,
+    /// Around the wheel Z axis, for a board lying flat. Drifts unless a
+    /// magnetometer is present.
+    Yaw,
+    /// Roll from gravity alone, without the gyro. Noisier and only good
+    /// for ±90°, but never drifts.
+    Tilt,
+}
+
+impl AxisMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(AxisMode::Roll),
+            1 => Some(AxisMode::Pitch),
+            2 => Some(AxisMode::Yaw),
+            3 => Some(AxisMode::Tilt),
+            _ => None,
+        }
+    }
+}
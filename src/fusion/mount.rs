@@ -0,0 +1,223 @@
+/// `Mount::axis_aligned` index that stands for a captured quaternion instead.
+pub const MOUNT_CAPTURED: u8 = 0xFF;
+
+/// Number of axis-aligned mounting orientations.
+pub const AXIS_ALIGNED_MOUNTS: u8 = 24;
+
+/// Smallest turn between the two mount calibration captures, in degrees.
+const MIN_CALIBRATION_TURN: f32 = 20.0;
+
+/// How the IMU sits in the wheel hub.
+///
+/// Rotates sensor readings into the wheel frame, where X runs along the
+/// steering column and Z points up with the wheel straight ahead, so the
+/// steering angle is always the roll of the fused orientation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mount {
+    /// Rows are the sensor-frame directions of the wheel X, Y and Z axes.
+    matrix: [[f32; 3]; 3],
+}
+
+impl Default for Mount {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mount {
+    /// Sensor X along the steering column, Z up.
+    pub const IDENTITY: Self = Self {
+        matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    };
+
+    /// One of the 24 orientations with the sensor axes along the wheel axes.
+    ///
+    /// `index / 4` picks the sensor axis along the column (+X, -X, +Y, -Y,
+    /// +Z, -Z), `index % 4` the remaining axis, in the same order, that
+    /// becomes the wheel Y axis. Index 0 is [`Mount::IDENTITY`].
+    pub fn axis_aligned(index: u8) -> Option<Self> {
+        if index >= AXIS_ALIGNED_MOUNTS {
+            return None;
+        }
+        let column = (index / 4) as usize;
+        let x = signed_axis(column);
+        let others: Vec<usize> = (0..6).filter(|&i| i / 2 != column / 2).collect();
+        let y = signed_axis(others[(index % 4) as usize]);
+        Some(Self {
+            matrix: [x, y, cross(x, y)],
+        })
+    }
+
+    /// Mount from a unit quaternion `[w, x, y, z]` that rotates the wheel
+    /// frame into the sensor frame.
+    pub fn from_quaternion(q: [f32; 4]) -> Option<Self> {
+        let norm = q.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if !norm.is_finite() || norm < 0.5 {
+            return None;
+        }
+        let [w, x, y, z] = q.map(|x| x / norm);
+        // Columns of the rotation matrix are the rows of the mount
+        Some(Self {
+            matrix: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y + w * z),
+                    2.0 * (x * z - w * y),
+                ],
+                [
+                    2.0 * (x * y - w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z + w * x),
+                ],
+                [
+                    2.0 * (x * z + w * y),
+                    2.0 * (y * z - w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                ],
+            ],
+        })
+    }
+
+    /// The inverse of [`Mount::from_quaternion`], used to persist the mount.
+    pub fn quaternion(&self) -> [f32; 4] {
+        let m = &self.matrix;
+        // r[i][j] of the wheel-to-sensor rotation is m[j][i]
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            [
+                0.25 * s,
+                (m[1][2] - m[2][1]) / s,
+                (m[2][0] - m[0][2]) / s,
+                (m[0][1] - m[1][0]) / s,
+            ]
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+            [
+                (m[1][2] - m[2][1]) / s,
+                0.25 * s,
+                (m[1][0] + m[0][1]) / s,
+                (m[2][0] + m[0][2]) / s,
+            ]
+        } else if m[1][1] > m[2][2] {
+            let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+            [
+                (m[2][0] - m[0][2]) / s,
+                (m[1][0] + m[0][1]) / s,
+                0.25 * s,
+                (m[2][1] + m[1][2]) / s,
+            ]
+        } else {
+            let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+            [
+                (m[0][1] - m[1][0]) / s,
+                (m[2][0] + m[0][2]) / s,
+                (m[2][1] + m[1][2]) / s,
+                0.25 * s,
+            ]
+        };
+        // Keep w positive so the stored form is unique
+        if q[0] < 0.0 {
+            q.map(|x| -x)
+        } else {
+            q
+        }
+    }
+
+    /// Rotates a sensor-frame vector into the wheel frame.
+    pub fn rotate(&self, v: [f32; 3]) -> [f32; 3] {
+        self.matrix.map(|row| dot(row, v))
+    }
+
+    /// Rotates a wheel-frame vector back into the sensor frame.
+    pub fn unrotate(&self, v: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|i| (0..3).map(|j| self.matrix[j][i] * v[j]).sum())
+    }
+}
+
+/// Result of one mount calibration capture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MountCapture {
+    /// Straight ahead captured, turn the wheel and capture again.
+    Centre,
+    /// Both captures done.
+    Done(Mount),
+    /// The wheel did not turn far enough between the captures, start over.
+    TurnTooSmall,
+}
+
+/// Finds the mount from gravity measured at two wheel positions.
+///
+/// The first capture, with the wheel straight ahead, fixes which way is
+/// up. The second, with the wheel turned, fixes the steering column as the
+/// axis gravity turned around. Roll then grows in the direction the wheel
+/// was turned.
+#[derive(Debug, Default)]
+pub struct MountCalibration {
+    centre: Option<[f32; 3]>,
+}
+
+impl MountCalibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the next capture of gravity, as averaged sensor-frame
+    /// accelerometer readings with the wheel held still.
+    pub fn capture(&mut self, gravity: [f32; 3]) -> MountCapture {
+        let Some(centre) = self.centre.take() else {
+            self.centre = Some(gravity);
+            return MountCapture::Centre;
+        };
+        let (Some(up), Some(turned)) = (normalize(centre), normalize(gravity)) else {
+            return MountCapture::TurnTooSmall;
+        };
+        let axis = cross(up, turned);
+        let turn = dot(axis, axis).sqrt().asin().to_degrees();
+        let Some(x) = normalize(axis).filter(|_| turn >= MIN_CALIBRATION_TURN) else {
+            return MountCapture::TurnTooSmall;
+        };
+        // Up without its part along the column, in case the column is tilted
+        let Some(z) = normalize(sub(up, scale(x, dot(up, x)))) else {
+            return MountCapture::TurnTooSmall;
+        };
+        MountCapture::Done(Mount {
+            matrix: [x, cross(z, x), z],
+        })
+    }
+}
+
+fn signed_axis(index: usize) -> [f32; 3] {
+    let mut v = [0.0; 3];
+    v[index / 2] = if index % 2 == 0 { 1.0 } else { -1.0 };
+    v
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn scale(v: [f32; 3], s: f32) -> [f32; 3] {
+    v.map(|x| x * s)
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn normalize(v: [f32; 3]) -> Option<[f32; 3]> {
+    let norm = dot(v, v).sqrt();
+    if norm > 1e-6 {
+        Some(scale(v, 1.0 / norm))
+    } else {
+        None
+    }
+}
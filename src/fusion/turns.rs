@@ -0,0 +1,61 @@
+/// Wraps an angle in degrees to [-180, 180).
+pub fn wrap_angle(angle: f32) -> f32 {
+    let angle = angle % 360.0;
+    if angle >= 180.0 {
+        angle - 360.0
+    } else if angle < -180.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
+}
+
+/// Follows an angle wrapped to a single turn across the ±180° boundary,
+/// and applies the zero offset.
+#[derive(Debug, Clone, Default)]
+pub struct TurnCounter {
+    angle: f32,
+    zero: f32,
+    delta: f32,
+}
+
+impl TurnCounter {
+    pub fn new() -> Self {
+        Self {
+            angle: 0.0,
+            zero: 0.0,
+            delta: 0.0,
+        }
+    }
+
+    /// Takes a new wrapped angle and returns the unwrapped angle
+    /// relative to the zero.
+    pub fn update(&mut self, wrapped: f32) -> f32 {
+        let mut delta = wrapped - wrap_angle(self.angle);
+        if delta > 180.0 {
+            delta -= 360.0;
+        } else if delta < -180.0 {
+            delta += 360.0;
+        }
+        self.delta = delta;
+        self.angle += delta;
+        self.angle - self.zero
+    }
+
+    /// The change of the last update, in degrees.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    pub fn set_zero(&mut self, zero: f32) {
+        self.zero = zero;
+        self.angle = zero;
+    }
+
+    pub fn recenter(&mut self) -> f32 {
+        let zero = wrap_angle(self.angle);
+        self.angle = zero;
+        self.zero = zero;
+        zero
+    }
+}
@@ -0,0 +1,428 @@
+//! Motion gestures recognised from IMU samples.
+
+/// Longest linear acceleration spike that counts as a tap, in seconds.
+const TAP_MAX_TIME: f32 = 0.06;
+/// Time between the two taps of a double-tap, in seconds.
+const DOUBLE_TAP_MIN: f32 = 0.08;
+const DOUBLE_TAP_MAX: f32 = 0.5;
+/// Time without another spike after a double-tap before it counts, in
+/// seconds, so the strokes of a shake are not taken for taps.
+const DOUBLE_TAP_SETTLE: f32 = 0.15;
+/// Strokes, in alternating directions, within `SHAKE_WINDOW` seconds that make a shake.
+const SHAKE_STROKES: u32 = 4;
+const SHAKE_WINDOW: f32 = 1.0;
+/// Longest rotation burst that counts as a flick, in seconds.
+const FLICK_MAX_TIME: f32 = 0.15;
+/// Time after a gesture in which no other is recognised, in seconds.
+const REFRACTORY_TIME: f32 = 0.5;
+/// Weight of each new sample in the gravity estimate that is taken off
+/// the accelerometer to get the linear acceleration.
+const GRAVITY_WEIGHT: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// Two quick taps on the rim.
+    DoubleTap,
+    /// Shaking the wheel back and forth.
+    Shake,
+    /// A short, sharp twist that stops again.
+    Flick,
+}
+
+impl Gesture {
+    pub const COUNT: usize = 3;
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Gesture::DoubleTap),
+            1 => Some(Gesture::Shake),
+            2 => Some(Gesture::Flick),
+            _ => None,
+        }
+    }
+}
+
+/// What a gesture does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureAction {
+    None,
+    /// Briefly presses a virtual HID button, by bit index.
+    Button(u8),
+    Recenter,
+    /// Switches to the next steering profile.
+    NextProfile,
+}
+
+/// Thresholds of the gestures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureConfig {
+    /// Linear acceleration of a tap, in g.
+    pub tap: f32,
+    /// Linear acceleration of each shake stroke, in g.
+    pub shake: f32,
+    /// Rotation rate of a flick, in rad/s.
+    pub flick: f32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            tap: 1.5,
+            shake: 1.2,
+            flick: 10.0,
+        }
+    }
+}
+
+impl GestureConfig {
+    pub fn threshold(&self, gesture: Gesture) -> f32 {
+        match gesture {
+            Gesture::DoubleTap => self.tap,
+            Gesture::Shake => self.shake,
+            Gesture::Flick => self.flick,
+        }
+    }
+
+    pub fn set_threshold(&mut self, gesture: Gesture, threshold: f32) {
+        match gesture {
+            Gesture::DoubleTap => self.tap = threshold,
+            Gesture::Shake => self.shake = threshold,
+            Gesture::Flick => self.flick = threshold,
+        }
+    }
+}
+
+/// Direction of the last shake stroke: axis and sign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stroke {
+    axis: usize,
+    positive: bool,
+}
+
+/// Recognises gestures from a stream of samples.
+///
+/// All timing is kept as time since the event in question, which stays
+/// small, rather than as absolute time, which would stop advancing in `f32`
+/// after a day or two of uptime.
+#[derive(Debug, Clone)]
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    gravity: Option<[f32; 3]>,
+    /// Time left before another gesture is recognised.
+    quiet: f32,
+    /// Time since the current acceleration spike started.
+    spike: Option<f32>,
+    /// Time since the last lone tap.
+    last_tap: Option<f32>,
+    /// Time since the second tap of a double-tap waiting to settle.
+    double_tap: Option<f32>,
+    stroke: Option<Stroke>,
+    strokes: u32,
+    /// Time since the first stroke of the current shake.
+    strokes_time: f32,
+    /// Time since the current rotation burst started.
+    flick: Option<f32>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            gravity: None,
+            quiet: 0.0,
+            spike: None,
+            last_tap: None,
+            double_tap: None,
+            stroke: None,
+            strokes: 0,
+            strokes_time: 0.0,
+            flick: None,
+        }
+    }
+
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    /// Feeds a sample of `accel` (g) and `gyro` (rad/s), taken `dt`
+    /// seconds after the previous one, and returns a gesture it completes.
+    pub fn update(&mut self, accel: [f32; 3], gyro: [f32; 3], dt: f32) -> Option<Gesture> {
+        let timers = [
+            &mut self.spike,
+            &mut self.last_tap,
+            &mut self.double_tap,
+            &mut self.flick,
+        ];
+        for timer in timers.into_iter().flatten() {
+            *timer += dt;
+        }
+        self.strokes_time += dt;
+        self.quiet = (self.quiet - dt).max(0.0);
+
+        let gravity = self.gravity.get_or_insert(accel);
+        for (g, a) in gravity.iter_mut().zip(accel) {
+            *g += (a - *g) * GRAVITY_WEIGHT;
+        }
+        let linear = [0, 1, 2].map(|i| accel[i] - gravity[i]);
+        if self.quiet > 0.0 {
+            return None;
+        }
+
+        let gesture = self
+            .double_tap(norm(linear))
+            .or_else(|| self.shake(linear))
+            .or_else(|| self.flick(norm(gyro)));
+        if gesture.is_some() {
+            self.quiet = REFRACTORY_TIME;
+            self.spike = None;
+            self.last_tap = None;
+            self.double_tap = None;
+            self.stroke = None;
+            self.strokes = 0;
+            self.flick = None;
+        }
+        gesture
+    }
+
+    fn double_tap(&mut self, linear: f32) -> Option<Gesture> {
+        if linear > self.config.tap {
+            // More spikes right after a double-tap make it something else
+            if self.double_tap.take().is_some() {
+                self.last_tap = None;
+            }
+            self.spike.get_or_insert(0.0);
+            return None;
+        }
+        if let Some(since) = self.double_tap {
+            if since >= DOUBLE_TAP_SETTLE {
+                self.double_tap = None;
+                return Some(Gesture::DoubleTap);
+            }
+        }
+        if self.spike.take()? > TAP_MAX_TIME {
+            return None;
+        }
+        match self.last_tap.replace(0.0) {
+            Some(since) if (DOUBLE_TAP_MIN..=DOUBLE_TAP_MAX).contains(&since) => {
+                self.last_tap = None;
+                self.double_tap = Some(0.0);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn shake(&mut self, linear: [f32; 3]) -> Option<Gesture> {
+        let (axis, value) = linear
+            .into_iter()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))?;
+        if value.abs() <= self.config.shake {
+            return None;
+        }
+        let stroke = Stroke {
+            axis,
+            positive: value > 0.0,
+        };
+        match self.stroke {
+            Some(last) if last == stroke => return None,
+            Some(last) if last.axis == axis && self.strokes_time <= SHAKE_WINDOW => {
+                self.strokes += 1;
+            }
+            _ => {
+                self.strokes = 1;
+                self.strokes_time = 0.0;
+            }
+        }
+        self.stroke = Some(stroke);
+        (self.strokes >= SHAKE_STROKES).then_some(Gesture::Shake)
+    }
+
+    fn flick(&mut self, rate: f32) -> Option<Gesture> {
+        if rate > self.config.flick {
+            self.flick.get_or_insert(0.0);
+            return None;
+        }
+        (self.flick.take()? <= FLICK_MAX_TIME).then_some(Gesture::Flick)
+    }
+}
+
+fn norm(v: [f32; 3]) -> f32 {
+    v.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.005;
+    const GRAVITY: [f32; 3] = [0.0, 0.0, 1.0];
+
+    /// Feeds a recognizer samples held for stretches of time, and keeps the
+    /// gestures it recognises.
+    struct Run {
+        recognizer: GestureRecognizer,
+        gestures: Vec<Gesture>,
+    }
+
+    impl Run {
+        fn new(config: GestureConfig) -> Self {
+            let mut run = Self {
+                recognizer: GestureRecognizer::new(config),
+                gestures: Vec::new(),
+            };
+            run.hold(GRAVITY, [0.0; 3], 0.5);
+            run
+        }
+
+        fn hold(&mut self, accel: [f32; 3], gyro: [f32; 3], time: f32) -> &mut Self {
+            for _ in 0..(time / DT).round() as u32 {
+                self.gestures
+                    .extend(self.recognizer.update(accel, gyro, DT));
+            }
+            self
+        }
+
+        fn still(&mut self, time: f32) -> &mut Self {
+            self.hold(GRAVITY, [0.0; 3], time)
+        }
+
+        fn tap(&mut self) -> &mut Self {
+            self.hold([0.0, 0.0, 3.0], [0.0; 3], 2.0 * DT)
+        }
+
+        fn shake(&mut self, strokes: u32) -> &mut Self {
+            for i in 0..strokes {
+                let x = if i % 2 == 0 { 2.5 } else { -2.5 };
+                self.hold([x, 0.0, 1.0], [0.0; 3], 4.0 * DT);
+                self.still(0.05);
+            }
+            self
+        }
+
+        fn twist(&mut self, rate: f32, time: f32) -> &mut Self {
+            self.hold(GRAVITY, [rate, 0.0, 0.0], time)
+        }
+    }
+
+    #[test]
+    fn recognises_double_tap() {
+        let mut run = Run::new(GestureConfig::default());
+        run.tap().still(0.2).tap();
+        // Only once nothing else followed for a moment
+        run.still(0.1);
+        assert!(run.gestures.is_empty());
+        run.still(0.1);
+        assert_eq!(run.gestures, [Gesture::DoubleTap]);
+    }
+
+    #[test]
+    fn ignores_single_and_spread_taps() {
+        let mut run = Run::new(GestureConfig::default());
+        run.tap().still(1.0);
+        // Too far apart, then too close together
+        run.tap().still(0.7).tap().still(1.0);
+        run.tap().still(0.03).tap().still(1.0);
+        assert!(run.gestures.is_empty(), "{:?}", run.gestures);
+    }
+
+    #[test]
+    fn long_push_is_not_a_tap() {
+        let mut run = Run::new(GestureConfig::default());
+        for _ in 0..2 {
+            run.hold([0.0, 0.0, 3.0], [0.0; 3], 0.1).still(0.2);
+        }
+        run.still(1.0);
+        assert!(
+            !run.gestures.contains(&Gesture::DoubleTap),
+            "{:?}",
+            run.gestures
+        );
+    }
+
+    #[test]
+    fn recognises_shake() {
+        let mut run = Run::new(GestureConfig::default());
+        run.shake(SHAKE_STROKES).still(1.0);
+        assert_eq!(run.gestures, [Gesture::Shake]);
+    }
+
+    #[test]
+    fn too_few_strokes_are_not_a_shake() {
+        let mut run = Run::new(GestureConfig::default());
+        run.shake(SHAKE_STROKES - 1).still(1.0);
+        assert!(
+            !run.gestures.contains(&Gesture::Shake),
+            "{:?}",
+            run.gestures
+        );
+    }
+
+    #[test]
+    fn recognises_flick_but_not_a_long_turn() {
+        let mut run = Run::new(GestureConfig::default());
+        run.twist(15.0, 0.1).still(0.1);
+        assert_eq!(run.gestures, [Gesture::Flick]);
+
+        let mut run = Run::new(GestureConfig::default());
+        run.twist(15.0, 0.5).still(0.1);
+        assert!(run.gestures.is_empty(), "{:?}", run.gestures);
+    }
+
+    #[test]
+    fn refractory_time_holds_off_the_next_gesture() {
+        let mut run = Run::new(GestureConfig::default());
+        run.twist(15.0, 0.1).still(0.2).twist(15.0, 0.1).still(0.1);
+        assert_eq!(run.gestures, [Gesture::Flick]);
+        run.still(REFRACTORY_TIME).twist(15.0, 0.1).still(0.1);
+        assert_eq!(run.gestures, [Gesture::Flick, Gesture::Flick]);
+    }
+
+    #[test]
+    fn keeps_working_after_days_of_uptime() {
+        let mut run = Run::new(GestureConfig::default());
+        // One long step stands in for days of samples
+        run.recognizer.update(GRAVITY, [0.0; 3], 200_000.0);
+        run.still(1.0).twist(15.0, 0.1).still(1.0);
+        run.tap().still(0.2).tap().still(1.0);
+        run.shake(SHAKE_STROKES).still(1.0);
+        assert_eq!(
+            run.gestures,
+            [Gesture::Flick, Gesture::DoubleTap, Gesture::Shake]
+        );
+    }
+
+    #[test]
+    fn thresholds_follow_the_config() {
+        let mut config = GestureConfig::default();
+        config.set_threshold(Gesture::Flick, 20.0);
+        config.set_threshold(Gesture::DoubleTap, 2.5);
+        assert_eq!(config.threshold(Gesture::Flick), 20.0);
+        let mut run = Run::new(config);
+        run.twist(15.0, 0.1).still(1.0);
+        run.tap().still(0.2).tap().still(1.0);
+        assert!(run.gestures.is_empty(), "{:?}", run.gestures);
+
+        // Lowered again on the fly
+        config.set_threshold(Gesture::Flick, 10.0);
+        config.set_threshold(Gesture::DoubleTap, 1.5);
+        run.recognizer.set_config(config);
+        assert_eq!(*run.recognizer.config(), config);
+        run.twist(15.0, 0.1).still(1.0);
+        run.tap().still(0.2).tap().still(1.0);
+        assert_eq!(run.gestures, [Gesture::Flick, Gesture::DoubleTap]);
+    }
+
+    #[test]
+    fn gesture_ids_round_trip() {
+        for i in 0..Gesture::COUNT as u8 {
+            let gesture = Gesture::from_u8(i).unwrap();
+            assert_eq!(Gesture::from_u8(gesture as u8), Some(gesture));
+        }
+        assert_eq!(Gesture::from_u8(Gesture::COUNT as u8), None);
+    }
+}
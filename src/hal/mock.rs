@@ -0,0 +1,221 @@
+//! Stand-ins for the hardware in host tests. Each mock is a handle that can
+//! be cloned, so a test keeps one to drive the input a driver owns.
+
+use super::AnalogInput;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use std::cell::{Cell, RefCell};
+use std::convert::Infallible;
+use std::rc::Rc;
+
+/// An analog input reading whatever it was last set to, in mV.
+#[derive(Debug, Clone, Default)]
+pub struct MockAnalog {
+    value: Rc<Cell<u16>>,
+}
+
+impl MockAnalog {
+    pub fn new(value: u16) -> Self {
+        Self {
+            value: Rc::new(Cell::new(value)),
+        }
+    }
+
+    pub fn set(&self, value: u16) {
+        self.value.set(value);
+    }
+}
+
+impl AnalogInput for MockAnalog {
+    fn read(&mut self) -> anyhow::Result<u16> {
+        Ok(self.value.get())
+    }
+}
+
+/// A digital pin, read as the level it was last driven or set to.
+#[derive(Debug, Clone)]
+pub struct MockPin {
+    high: Rc<Cell<bool>>,
+}
+
+impl MockPin {
+    pub fn new(high: bool) -> Self {
+        Self {
+            high: Rc::new(Cell::new(high)),
+        }
+    }
+
+    pub fn set_high(&self, high: bool) {
+        self.high.set(high);
+    }
+}
+
+impl ErrorType for MockPin {
+    type Error = Infallible;
+}
+
+impl InputPin for MockPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.high.get())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.high.get())
+    }
+}
+
+impl OutputPin for MockPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.high.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.high.set(true);
+        Ok(())
+    }
+}
+
+/// A delay that returns at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MockDelay;
+
+impl DelayNs for MockDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+#[derive(Debug, Default)]
+struct Matrix {
+    diodes: bool,
+    pressed: Vec<(usize, usize)>,
+    low_rows: Vec<usize>,
+}
+
+impl Matrix {
+    /// Whether a column is pulled low through the pressed switches: straight
+    /// from a low row with diodes, or through any chain of switches without.
+    fn column_low(&self, col: usize) -> bool {
+        if self.diodes {
+            return self
+                .pressed
+                .iter()
+                .any(|(row, c)| *c == col && self.low_rows.contains(row));
+        }
+        let mut rows = self.low_rows.clone();
+        let mut cols = Vec::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(row, c) in &self.pressed {
+                let (has_row, has_col) = (rows.contains(&row), cols.contains(&c));
+                if has_row && !has_col {
+                    cols.push(c);
+                    changed = true;
+                } else if has_col && !has_row {
+                    rows.push(row);
+                    changed = true;
+                }
+            }
+        }
+        cols.contains(&col)
+    }
+}
+
+/// A keypad matrix of switches between row outputs and pulled-up column
+/// inputs, with or without a diode on each switch.
+#[derive(Debug, Clone, Default)]
+pub struct MockMatrix {
+    matrix: Rc<RefCell<Matrix>>,
+}
+
+impl MockMatrix {
+    pub fn new(diodes: bool) -> Self {
+        let matrix = Matrix {
+            diodes,
+            ..Default::default()
+        };
+        Self {
+            matrix: Rc::new(RefCell::new(matrix)),
+        }
+    }
+
+    pub fn press(&self, row: usize, col: usize) {
+        let mut matrix = self.matrix.borrow_mut();
+        if !matrix.pressed.contains(&(row, col)) {
+            matrix.pressed.push((row, col));
+        }
+    }
+
+    pub fn release(&self, row: usize, col: usize) {
+        self.matrix
+            .borrow_mut()
+            .pressed
+            .retain(|key| *key != (row, col));
+    }
+
+    pub fn row(&self, row: usize) -> MockRow {
+        MockRow {
+            matrix: self.clone(),
+            row,
+        }
+    }
+
+    pub fn col(&self, col: usize) -> MockCol {
+        MockCol {
+            matrix: self.clone(),
+            col,
+        }
+    }
+}
+
+/// A row output of a [`MockMatrix`].
+#[derive(Debug, Clone)]
+pub struct MockRow {
+    matrix: MockMatrix,
+    row: usize,
+}
+
+impl ErrorType for MockRow {
+    type Error = Infallible;
+}
+
+impl OutputPin for MockRow {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let mut matrix = self.matrix.matrix.borrow_mut();
+        if !matrix.low_rows.contains(&self.row) {
+            matrix.low_rows.push(self.row);
+        }
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let row = self.row;
+        self.matrix
+            .matrix
+            .borrow_mut()
+            .low_rows
+            .retain(|low| *low != row);
+        Ok(())
+    }
+}
+
+/// A column input of a [`MockMatrix`].
+#[derive(Debug, Clone)]
+pub struct MockCol {
+    matrix: MockMatrix,
+    col: usize,
+}
+
+impl ErrorType for MockCol {
+    type Error = Infallible;
+}
+
+impl InputPin for MockCol {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.is_low().map(|low| !low)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.matrix.matrix.borrow().column_low(self.col))
+    }
+}
@@ -0,0 +1,22 @@
+//! What the control drivers need from the hardware, so that they build and
+//! test on the host as well.
+//!
+//! Digital pins and delays are the `embedded-hal` traits; analog inputs,
+//! which `embedded-hal` 1.0 has no trait for, are [`AnalogInput`].
+
+use std::fmt::Debug;
+
+#[cfg(test)]
+pub mod mock;
+
+/// An analog input, such as an ADC channel.
+pub trait AnalogInput {
+    /// Reads the input, in mV.
+    fn read(&mut self) -> anyhow::Result<u16>;
+}
+
+/// Wraps the error of an `embedded-hal` driver, which need not implement
+/// `std::error::Error`.
+pub(crate) fn hal_error<E: Debug>(e: E) -> anyhow::Error {
+    anyhow::anyhow!("{:?}", e)
+}
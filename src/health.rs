@@ -0,0 +1,349 @@
+//! Sensor health checks and the diagnostics report.
+
+use bitflags::bitflags;
+use zerocopy_derive::{Immutable, IntoBytes};
+
+/// Self-test response, relative to the factory one, that passes.
+const SELF_TEST_MIN: f32 = 0.5;
+const SELF_TEST_MAX: f32 = 1.5;
+/// Accelerometer norm at rest that passes, in g.
+const ACCEL_NORM_MIN: f32 = 0.9;
+const ACCEL_NORM_MAX: f32 = 1.1;
+/// Largest gyro noise at rest, as standard deviation in rad/s (about 1 dps).
+const GYRO_NOISE_MAX: f32 = 0.02;
+/// Smoothed gyro rate, in rad/s, above which the wheel is being turned.
+const MOVING_RATE: f32 = 0.1;
+/// Change of the smoothed acceleration on any axis, in g, from the start of
+/// the samples at rest, past which the wheel was turned.
+const MOVING_TILT: f32 = 0.05;
+/// Weight of each new sample in the smoothed rate and acceleration, so that
+/// noise alone does not read as moving.
+const MOVING_WEIGHT: f32 = 0.05;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct HealthFlags: u8 {
+        /// The sensor is lost or was not found, and is being recovered, or
+        /// one of two redundant sensors is out and the other carries on alone.
+        const DEGRADED        = 1 << 0;
+        /// The checks at rest have not finished yet.
+        const CHECKING        = 1 << 1;
+        const GYRO_SELF_TEST  = 1 << 2;
+        const ACCEL_SELF_TEST = 1 << 3;
+        const ACCEL_NORM      = 1 << 4;
+        const GYRO_NOISE      = 1 << 5;
+        /// Two redundant sensors disagree, so only the quieter one is used.
+        const DISAGREE        = 1 << 6;
+        /// The self-test has not run yet, or could not run, and is tried
+        /// again once the wheel is at rest. Unlike a failure, nothing is
+        /// known to be wrong with the sensor.
+        const UNTESTED        = 1 << 7;
+        /// Any failed check, which makes the sensor unusable for steering.
+        const FAILED = Self::GYRO_SELF_TEST.bits()
+            | Self::ACCEL_SELF_TEST.bits()
+            | Self::ACCEL_NORM.bits()
+            | Self::GYRO_NOISE.bits();
+    }
+}
+
+/// Factory self-test response of the MPU6500 family from the trim code in
+/// the self-test registers, in LSB at the lowest full scale range.
+pub fn factory_self_test(code: u8) -> f32 {
+    if code == 0 {
+        // Not trimmed, so no response can pass
+        return f32::INFINITY;
+    }
+    2620.0 * 1.01_f32.powi(code as i32 - 1)
+}
+
+/// Accumulates samples taken at rest. Whenever the samples show the wheel
+/// moving, the ones so far are dropped and the check starts over.
+#[derive(Debug, Clone, Default)]
+pub struct RestCheck {
+    count: u32,
+    time: f32,
+    accel_norm: f32,
+    gyro: [f32; 3],
+    gyro_sq: [f32; 3],
+    /// Smoothed acceleration and rate, to tell movement from noise.
+    accel: [f32; 3],
+    rate: [f32; 3],
+    /// Smoothed acceleration when the samples at rest started.
+    reference: Option<[f32; 3]>,
+}
+
+impl RestCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sample of `accel` (g) and `gyro` (rad/s), taken `dt` seconds
+    /// after the previous one.
+    pub fn add(&mut self, accel: [f32; 3], gyro: [f32; 3], dt: f32) {
+        if self.reference.is_none() {
+            self.accel = accel;
+            self.rate = gyro;
+        }
+        for i in 0..3 {
+            self.accel[i] += (accel[i] - self.accel[i]) * MOVING_WEIGHT;
+            self.rate[i] += (gyro[i] - self.rate[i]) * MOVING_WEIGHT;
+        }
+        let reference = *self.reference.get_or_insert(self.accel);
+        let rate = self.rate.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let tilted = (0..3).any(|i| (self.accel[i] - reference[i]).abs() > MOVING_TILT);
+        if rate > MOVING_RATE || tilted {
+            // Start over from here, keeping the smoothed values
+            *self = Self {
+                accel: self.accel,
+                rate: self.rate,
+                reference: Some(self.accel),
+                ..Self::default()
+            };
+            return;
+        }
+
+        self.count += 1;
+        self.time += dt;
+        self.accel_norm += accel.iter().map(|x| x * x).sum::<f32>().sqrt();
+        for (i, x) in gyro.into_iter().enumerate() {
+            self.gyro[i] += x;
+            self.gyro_sq[i] += x * x;
+        }
+    }
+
+    /// Seconds of samples added since the wheel last moved.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn accel_norm(&self) -> f32 {
+        self.accel_norm / self.count.max(1) as f32
+    }
+
+    /// The largest standard deviation of the gyro axes.
+    pub fn gyro_noise(&self) -> f32 {
+        let n = self.count.max(1) as f32;
+        (0..3)
+            .map(|i| {
+                let mean = self.gyro[i] / n;
+                (self.gyro_sq[i] / n - mean * mean).max(0.0).sqrt()
+            })
+            .fold(0.0, f32::max)
+    }
+}
+
+/// Results of the sensor checks.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Health {
+    pub flags: HealthFlags,
+    /// Self-test responses relative to the factory ones.
+    pub gyro_self_test: [f32; 3],
+    pub accel_self_test: [f32; 3],
+    /// Mean accelerometer norm at rest, in g.
+    pub accel_norm: f32,
+    /// Gyro noise at rest, in rad/s.
+    pub gyro_noise: f32,
+    /// Bits of the sensors in use, by index, for sources with more than one.
+    pub sensors: u8,
+}
+
+impl Health {
+    /// Whether any check failed.
+    pub fn failed(&self) -> bool {
+        self.flags.intersects(HealthFlags::FAILED)
+    }
+
+    /// Whether a check at rest failed, which unlike the self-test is worth
+    /// running again, as the wheel may just not have been left alone.
+    pub fn rest_failed(&self) -> bool {
+        self.flags
+            .intersects(HealthFlags::ACCEL_NORM | HealthFlags::GYRO_NOISE)
+    }
+
+    /// Whether the self-test is still to run.
+    pub fn untested(&self) -> bool {
+        self.flags.contains(HealthFlags::UNTESTED)
+    }
+
+    /// Records the self-test responses, relative to the factory ones.
+    pub fn self_test(&mut self, gyro: [f32; 3], accel: [f32; 3]) {
+        let passes = |ratios: [f32; 3]| {
+            ratios
+                .iter()
+                .all(|r| (SELF_TEST_MIN..=SELF_TEST_MAX).contains(r))
+        };
+        self.gyro_self_test = gyro;
+        self.accel_self_test = accel;
+        self.flags.set(HealthFlags::GYRO_SELF_TEST, !passes(gyro));
+        self.flags.set(HealthFlags::ACCEL_SELF_TEST, !passes(accel));
+        self.flags.remove(HealthFlags::UNTESTED);
+    }
+
+    /// Records the checks at rest.
+    pub fn rest(&mut self, check: &RestCheck) {
+        self.accel_norm = check.accel_norm();
+        self.gyro_noise = check.gyro_noise();
+        self.flags.remove(HealthFlags::CHECKING);
+        self.flags.set(
+            HealthFlags::ACCEL_NORM,
+            !(ACCEL_NORM_MIN..=ACCEL_NORM_MAX).contains(&self.accel_norm),
+        );
+        self.flags
+            .set(HealthFlags::GYRO_NOISE, self.gyro_noise > GYRO_NOISE_MAX);
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        let percent = |ratios: [f32; 3]| ratios.map(|r| (r * 100.0).clamp(0.0, 255.0) as u8);
+        Diagnostics {
+            flags: self.flags.bits(),
+            gyro_self_test: percent(self.gyro_self_test),
+            accel_self_test: percent(self.accel_self_test),
+            accel_norm: (self.accel_norm * 1000.0).clamp(0.0, u16::MAX as f32) as u16,
+            gyro_noise: (self.gyro_noise.to_degrees() * 1000.0).clamp(0.0, u16::MAX as f32) as u16,
+            sensors: self.sensors,
+        }
+    }
+}
+
+/// Value of the diagnostics characteristic.
+#[derive(IntoBytes, Immutable, Debug, Clone, Copy, Default)]
+#[repr(C, packed)]
+pub struct Diagnostics {
+    /// [`HealthFlags`] bits.
+    pub flags: u8,
+    /// Self-test responses in percent of the factory ones.
+    pub gyro_self_test: [u8; 3],
+    pub accel_self_test: [u8; 3],
+    /// Mean accelerometer norm at rest, in mg.
+    pub accel_norm: u16,
+    /// Gyro noise at rest, in mdps.
+    pub gyro_noise: u16,
+    /// Bits of the sensors in use, by index.
+    pub sensors: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    /// Adds `time` seconds of samples, with the accel and gyro of each given
+    /// by a function of the time since the start.
+    fn add(check: &mut RestCheck, time: f32, sample: impl Fn(f32) -> ([f32; 3], [f32; 3])) {
+        for i in 0..(time / DT) as u32 {
+            let (accel, gyro) = sample(i as f32 * DT);
+            check.add(accel, gyro, DT);
+        }
+    }
+
+    /// Noise of about the given standard deviation, zero on average.
+    fn noise(t: f32, sd: f32) -> f32 {
+        (t * 1234.5).sin() * sd * std::f32::consts::SQRT_2
+    }
+
+    fn still(t: f32) -> ([f32; 3], [f32; 3]) {
+        (
+            [0.0, 0.0, 1.0],
+            [0.01, -0.005, 0.0].map(|b| b + noise(t, 0.002)),
+        )
+    }
+
+    #[test]
+    fn passes_at_rest() {
+        let mut check = RestCheck::new();
+        add(&mut check, 1.0, still);
+        assert!((check.time() - 1.0).abs() < 0.01, "{}", check.time());
+        let mut health = Health {
+            flags: HealthFlags::CHECKING,
+            ..Default::default()
+        };
+        health.rest(&check);
+        assert_eq!(health.flags, HealthFlags::empty());
+        assert!((health.accel_norm - 1.0).abs() < 1e-3);
+        assert!(health.gyro_noise < 0.005, "{}", health.gyro_noise);
+    }
+
+    #[test]
+    fn fails_noisy_gyro_and_bad_accel() {
+        let mut check = RestCheck::new();
+        // Too noisy, but not enough to look like the wheel moving
+        add(&mut check, 1.0, |t| {
+            ([0.0, 0.0, 0.8], [noise(t, 0.04), 0.0, 0.0])
+        });
+        assert!((check.time() - 1.0).abs() < 0.01, "{}", check.time());
+        let mut health = Health::default();
+        health.rest(&check);
+        assert!(health.flags.contains(HealthFlags::GYRO_NOISE));
+        assert!(health.flags.contains(HealthFlags::ACCEL_NORM));
+        assert!(health.failed() && health.rest_failed());
+    }
+
+    #[test]
+    fn starts_over_while_turning() {
+        let mut check = RestCheck::new();
+        // Turned steadily at power-on, gravity turning with the wheel
+        add(&mut check, 2.0, |t| {
+            let angle = 0.5 * t;
+            ([0.0, -angle.sin(), angle.cos()], [0.5, 0.0, 0.0])
+        });
+        assert!(check.time() < 0.1, "{}", check.time());
+        // Turned about the vertical, where gravity stays put
+        add(&mut check, 1.0, |_| ([0.0, 0.0, 1.0], [0.0, 0.0, 0.3]));
+        assert!(check.time() < 0.1, "{}", check.time());
+        // Then left alone, which is all that is checked
+        add(&mut check, 1.0, still);
+        assert!(check.time() > 0.9, "{}", check.time());
+        let mut health = Health::default();
+        health.rest(&check);
+        assert!(!health.failed(), "{health:?}");
+    }
+
+    #[test]
+    fn starts_over_when_tilted() {
+        let mut check = RestCheck::new();
+        add(&mut check, 0.5, still);
+        // Gravity moved to another axis, with little rate left to see
+        add(&mut check, 0.5, |t| ([0.0, -0.3, 0.95], still(t).1));
+        assert!(check.time() < 0.5, "{}", check.time());
+    }
+
+    #[test]
+    fn rerun_clears_failure() {
+        let mut health = Health::default();
+        let mut check = RestCheck::new();
+        add(&mut check, 1.0, |t| {
+            ([0.0, 0.0, 1.0], [noise(t, 0.04), 0.0, 0.0])
+        });
+        health.rest(&check);
+        assert!(health.rest_failed());
+
+        let mut check = RestCheck::new();
+        add(&mut check, 1.0, still);
+        health.rest(&check);
+        assert!(!health.failed(), "{health:?}");
+    }
+
+    #[test]
+    fn self_test_failure_is_not_a_rest_failure() {
+        let mut health = Health::default();
+        health.self_test([1.0, 0.2, 1.0], [1.0; 3]);
+        assert!(health.failed());
+        assert!(!health.rest_failed());
+        health.self_test([1.0; 3], [1.0, 1.2, 0.9]);
+        assert!(!health.failed());
+    }
+
+    #[test]
+    fn untested_is_not_a_failure() {
+        let mut health = Health {
+            flags: HealthFlags::UNTESTED,
+            ..Health::default()
+        };
+        assert!(health.untested());
+        assert!(!health.failed());
+        health.self_test([0.2; 3], [1.0; 3]);
+        assert!(!health.untested());
+        assert!(health.failed());
+    }
+}
@@ -0,0 +1,46 @@
+use esp32_ble_steering_rs::hal::AnalogInput;
+use esp_idf_hal::adc::attenuation::DB_11;
+use esp_idf_hal::adc::oneshot::config::AdcChannelConfig;
+use esp_idf_hal::adc::oneshot::config::Calibration::Line;
+use esp_idf_hal::adc::oneshot::{AdcChannelDriver, AdcDriver};
+use esp_idf_hal::adc::Resolution::Resolution12Bit;
+use esp_idf_hal::gpio::{ADCPin, Input, InputPin, OutputPin, PinDriver, Pull};
+
+/// An ADC channel read in mV, over the full 0-3.3 V range.
+pub struct EspAnalog<'a, T: ADCPin> {
+    channel: AdcChannelDriver<'a, T, &'a AdcDriver<'a, T::Adc>>,
+}
+
+impl<'a, T: ADCPin> EspAnalog<'a, T> {
+    pub fn new(adc: &'a AdcDriver<'a, T::Adc>, pin: T) -> anyhow::Result<Self> {
+        let config = AdcChannelConfig {
+            attenuation: DB_11,
+            resolution: Resolution12Bit,
+            calibration: Line,
+        };
+        Ok(Self {
+            channel: AdcChannelDriver::new(adc, pin, &config)?,
+        })
+    }
+}
+
+impl<T: ADCPin> AnalogInput for EspAnalog<'_, T> {
+    fn read(&mut self) -> anyhow::Result<u16> {
+        Ok(self.channel.read()?)
+    }
+}
+
+/// An input pin for a switch, pulled up for one that pulls it low when
+/// pressed, or down with `invert`.
+pub fn switch_pin<'a, T: InputPin + OutputPin>(
+    pin: T,
+    invert: bool,
+) -> anyhow::Result<PinDriver<'a, T, Input>> {
+    let mut pin = PinDriver::input(pin)?;
+    if invert {
+        pin.set_pull(Pull::Down)?;
+    } else {
+        pin.set_pull(Pull::Up)?;
+    }
+    Ok(pin)
+}
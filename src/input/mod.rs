@@ -1,11 +1,7 @@
-mod joystick;
-pub use joystick::*;
+pub use esp32_ble_steering_rs::controls::*;
 
-mod keypad;
-pub use keypad::*;
+mod esp;
+pub use esp::*;
 
-mod pedal;
-pub use pedal::*;
-
-mod button;
-pub use button::*;
\ No newline at end of file
+mod virtual_buttons;
+pub use virtual_buttons::*;
@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+
+/// HID buttons pressed by something other than a switch, such as a gesture,
+/// for a fixed time.
+#[derive(Default)]
+pub struct VirtualButtons {
+    until: [Option<Instant>; 64],
+}
+
+impl VirtualButtons {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Presses the button with bit `index` for `duration`.
+    pub fn press(&mut self, index: u8, duration: Duration) {
+        if let Some(until) = self.until.get_mut(index as usize) {
+            *until = Some(Instant::now() + duration);
+        }
+    }
+
+    /// Bits of the buttons currently pressed.
+    pub fn states(&mut self) -> u64 {
+        let now = Instant::now();
+        let mut states = 0;
+        for (i, until) in self.until.iter_mut().enumerate() {
+            match until {
+                Some(t) if *t > now => states |= 1 << i,
+                _ => *until = None,
+            }
+        }
+        states
+    }
+}
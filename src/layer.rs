@@ -0,0 +1,128 @@
+//! A second bank of keypad buttons, switched by a shift input.
+
+/// How the shift input switches banks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerMode {
+    /// The second bank is active while the shift input is held.
+    Momentary,
+    /// Each press of the shift input switches to the other bank.
+    Toggle,
+}
+
+impl LayerMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(LayerMode::Momentary),
+            1 => Some(LayerMode::Toggle),
+            _ => None,
+        }
+    }
+}
+
+/// What switches banks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftInput {
+    /// A keypad key, by row and column.
+    Key(usize, usize),
+    /// Another button, such as a paddle, by HID bit index.
+    Button(u8),
+}
+
+/// Tracks which bank of buttons the keypad presses.
+#[derive(Debug, Clone)]
+pub struct ShiftLayer {
+    mode: LayerMode,
+    held: bool,
+    shifted: bool,
+}
+
+impl ShiftLayer {
+    pub fn new(mode: LayerMode) -> Self {
+        Self {
+            mode,
+            held: false,
+            shifted: false,
+        }
+    }
+
+    pub fn mode(&self) -> LayerMode {
+        self.mode
+    }
+
+    /// Changes the mode, going back to the first bank.
+    pub fn set_mode(&mut self, mode: LayerMode) {
+        self.mode = mode;
+        self.shifted = false;
+    }
+
+    /// Whether the second bank is active.
+    pub fn shifted(&self) -> bool {
+        self.shifted
+    }
+
+    /// Takes whether the shift input is held, and returns whether the
+    /// second bank is active now.
+    pub fn update(&mut self, held: bool) -> bool {
+        match self.mode {
+            LayerMode::Momentary => self.shifted = held,
+            LayerMode::Toggle if held && !self.held => self.shifted = !self.shifted,
+            LayerMode::Toggle => {}
+        }
+        self.held = held;
+        self.shifted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn momentary_follows_the_shift_input() {
+        let mut layer = ShiftLayer::new(LayerMode::Momentary);
+        assert!(!layer.update(false));
+        assert!(layer.update(true));
+        assert!(layer.update(true));
+        assert!(layer.shifted());
+        assert!(!layer.update(false));
+        assert!(!layer.shifted());
+    }
+
+    #[test]
+    fn toggle_flips_on_the_press_only() {
+        let mut layer = ShiftLayer::new(LayerMode::Toggle);
+        assert!(layer.update(true));
+        // Held and released, it stays
+        assert!(layer.update(true));
+        assert!(layer.update(false));
+        assert!(!layer.update(true));
+        assert!(!layer.update(false));
+        assert!(!layer.shifted());
+    }
+
+    #[test]
+    fn set_mode_goes_back_to_the_first_bank() {
+        let mut layer = ShiftLayer::new(LayerMode::Toggle);
+        layer.update(true);
+        assert!(layer.shifted());
+        layer.set_mode(LayerMode::Momentary);
+        assert_eq!(layer.mode(), LayerMode::Momentary);
+        assert!(!layer.shifted());
+
+        layer.update(true);
+        layer.set_mode(LayerMode::Toggle);
+        assert!(!layer.shifted());
+        // Still held from before, so no new press to flip on
+        assert!(!layer.update(true));
+        assert!(!layer.update(false));
+        assert!(layer.update(true));
+    }
+
+    #[test]
+    fn mode_ids_round_trip() {
+        for mode in [LayerMode::Momentary, LayerMode::Toggle] {
+            assert_eq!(LayerMode::from_u8(mode as u8), Some(mode));
+        }
+        assert_eq!(LayerMode::from_u8(2), None);
+    }
+}
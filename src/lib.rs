@@ -0,0 +1,16 @@
+//! Platform-independent parts of the firmware.
+//!
+//! Everything in here builds for the host as well, so it can be tested with
+//! `cargo test --lib --target x86_64-unknown-linux-gnu` (or your host's target).
+
+pub mod actions;
+pub mod command;
+pub mod controls;
+pub mod debounce;
+pub mod fallback;
+pub mod filter;
+pub mod fusion;
+pub mod gesture;
+pub mod hal;
+pub mod health;
+pub mod layer;
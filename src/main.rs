@@ -1,70 +1,385 @@
+use esp32_ble_steering_rs::actions::{ChordBinding, KeyActions, KeyBinding, KeyTimings};
+use esp32_ble_steering_rs::fallback::{Active, FallbackKind, KeyAxis, SourcePolicy};
+use esp32_ble_steering_rs::filter::{Chain, Filter};
+use esp32_ble_steering_rs::fusion::{
+    AxisMode, GyroTempModel, Mount, MountCalibration, MountCapture, MOUNT_CAPTURED,
+};
+use esp32_ble_steering_rs::gesture::{Gesture, GestureAction, GestureConfig};
+use esp32_ble_steering_rs::health::HealthFlags;
+use esp32_ble_steering_rs::layer::{LayerMode, ShiftInput, ShiftLayer};
 use esp_idf_hal::adc::oneshot::AdcDriver;
+use esp_idf_hal::delay::Ets;
 use esp_idf_hal::gpio::{IOPin, OutputPin, PinDriver};
 use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
 use esp_idf_hal::peripherals::Peripherals;
+use esp_idf_hal::spi::config::{Config as SpiConfig, DriverConfig as SpiDriverConfig, MODE_1};
+use esp_idf_hal::spi::SpiDeviceDriver;
 use esp_idf_hal::task::block_on;
 use esp_idf_hal::timer::{TimerConfig, TimerDriver};
 use esp_idf_hal::units::Hertz;
 use futures::join;
 use log::{info, warn};
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
 
 mod sensors;
-use sensors::MpuSensor;
+use sensors::{As5048, As5600, Dlpf, I2cBus, ImuConfig, MpuSensor, SourceKind, SteeringSource};
 
 mod input;
-use input::Button;
 use input::Joystick;
-use input::Keypad;
 use input::Pedal;
+use input::VirtualButtons;
+use input::{switch_pin, AxisRange, Button, EspAnalog, JoystickCalibration, JoystickCapture};
+use input::{AxisResponse, DeadzoneMode, StickResponse};
+use input::{KeyMap, KeyState, Keypad};
 
 mod output;
-use output::Switch;
+use output::{Haptic, Status, StatusLed, Switch};
 
 mod ble;
-use ble::Steering;
+use ble::{Axis, Command, Steering};
+
+mod config;
+use config::{Hold, Store, DEFAULT_PROFILE, PROFILES};
 
 const AX_MAX: i16 = 32767;
 const AX_MIN: i16 = -32767;
 const SM_MAX: i16 = 32767;
 const SM_MIN: i16 = 0;
-const STEERING_ROTATION_ANGLE: f32 = 900.0;
+
+const PROFILE_KEY: &str = "profile";
+const SOURCE_KEY: &str = "source";
+const INVERT_KEY: &str = "src_invert";
+const IMU_RATE_KEY: &str = "imu_rate";
+const IMU_DLPF_KEY: &str = "imu_dlpf";
+const MOUNT_KEY: &str = "mount";
+const MOUNT_QUAT_KEYS: [&str; 4] = ["mount_qw", "mount_qx", "mount_qy", "mount_qz"];
+// Per IMU, the second one being at the alternate address
+const GYRO_OFFSET_KEYS: [[&str; 3]; 2] = [
+    ["gyro_off_x", "gyro_off_y", "gyro_off_z"],
+    ["gyro1_off_x", "gyro1_off_y", "gyro1_off_z"],
+];
+const GYRO_SLOPE_KEYS: [[&str; 3]; 2] = [
+    ["gyro_slope_x", "gyro_slope_y", "gyro_slope_z"],
+    ["gyro1_slope_x", "gyro1_slope_y", "gyro1_slope_z"],
+];
+const LOCK_BUZZ_TIME: Duration = Duration::from_millis(150);
+// Without a stored zero, recenter once the wheel has been still this long after boot
+const BOOT_STILL_TIME: Duration = Duration::from_secs(5);
+// Both gear paddles held together recenter the steering
+const RECENTER_HOLD_TIME: Duration = Duration::from_secs(2);
+// The gear microswitches bounce for longer than the keypad switches
+const GEAR_DEBOUNCE_TIME: Duration = Duration::from_millis(20);
+// Gestures press virtual buttons past the physical ones by default
+const DEFAULT_GESTURE_ACTIONS: [GestureAction; Gesture::COUNT] = [
+    GestureAction::Button(29), // Double-tap
+    GestureAction::Button(30), // Shake
+    GestureAction::Button(31), // Flick
+];
+const GESTURE_PRESS_TIME: Duration = Duration::from_millis(100);
+// Mount calibration captures gravity, so the wheel has to be still
+const MOUNT_STILL_TIME: Duration = Duration::from_secs(1);
+// One keypad row per tick, so a full scan of the 4 rows takes 4 ms
+const KEYPAD_ROW_US: u64 = 1000;
+const KEYPAD_SETTLE_US: u32 = 10;
+// The keypad switches in the pinouts are wired without diodes
+const KEYPAD_HAS_DIODES: bool = false;
+const FALLBACK_KEY: &str = "fallback";
+// Bottom-left and bottom-right keypad keys, by row and column, steer when
+// falling back to keys
+const FALLBACK_LEFT_KEY: (usize, usize) = (3, 0);
+const FALLBACK_RIGHT_KEY: (usize, usize) = (3, 3);
+// HID button bit of each keypad key, by row and column
+const KEYMAP: KeyMap<4, 4> = [
+    [Some(0), Some(1), Some(2), Some(3)],
+    [Some(4), Some(5), Some(6), Some(7)],
+    [Some(8), Some(9), Some(10), Some(11)],
+    [Some(12), Some(13), Some(14), Some(15)],
+];
+// The top row keys press more buttons past the physical ones on a long-press
+// or a double-tap, and the two outer ones together another
+const KEY_BINDINGS: [KeyBinding; 4] = [
+    KeyBinding {
+        key: (0, 0),
+        tap: Some(0),
+        long_press: Some(32),
+        double_tap: Some(36),
+    },
+    KeyBinding {
+        key: (0, 1),
+        tap: Some(1),
+        long_press: Some(33),
+        double_tap: Some(37),
+    },
+    KeyBinding {
+        key: (0, 2),
+        tap: Some(2),
+        long_press: Some(34),
+        double_tap: Some(38),
+    },
+    KeyBinding {
+        key: (0, 3),
+        tap: Some(3),
+        long_press: Some(35),
+        double_tap: Some(39),
+    },
+];
+const KEY_CHORDS: [ChordBinding; 1] = [ChordBinding {
+    keys: [(0, 0), (0, 3)],
+    button: 40,
+}];
+const KEY_ACTION_PRESS_TIME: Duration = Duration::from_millis(100);
+// Holding or toggling the shift key switches the keypad to its second bank
+const SHIFT_INPUT: ShiftInput = ShiftInput::Key(3, 1);
+const SHIFTED_KEYMAP: KeyMap<4, 4> = [
+    [Some(41), Some(42), Some(43), Some(44)],
+    [Some(45), Some(46), Some(47), Some(48)],
+    [Some(49), Some(50), Some(51), Some(52)],
+    [Some(53), Some(54), Some(55), Some(56)],
+];
+const LAYER_MODE_KEY: &str = "layer_mode";
+const LAYER_BUZZ_TIME: Duration = Duration::from_millis(60);
+// Joystick deadzone, as a fraction of full deflection
+const JOYSTICK_DEADZONE: f32 = 0.05;
+// Joystick axis ends and centre, in mV, by axis
+const JOYSTICK_KEYS: [[&str; 3]; 2] = [
+    ["joy_x_min", "joy_x_mid", "joy_x_max"],
+    ["joy_y_min", "joy_y_mid", "joy_y_max"],
+];
+
+/// NVS key of the steering zero, which depends on what is measured.
+fn zero_key_for(source: SourceKind, mode: AxisMode) -> &'static str {
+    match (source, mode) {
+        (SourceKind::Imu, AxisMode::Roll) => "roll_zero",
+        (SourceKind::Imu, AxisMode::Pitch) => "pitch_zero",
+        (SourceKind::Imu, AxisMode::Yaw) => "yaw_zero",
+        (SourceKind::Imu, AxisMode::Tilt) => "tilt_zero",
+        (SourceKind::As5600, _) => "as5600_zero",
+        (SourceKind::As5048, _) => "as5048_zero",
+    }
+}
+
+/// Sets the stored steering zero on `source`, returning whether there was one.
+/// Without one, the zero and turn count are cleared, so that nothing is
+/// carried over from another axis mode until the wheel is recentered.
+fn load_zero(store: &Store, source: &mut dyn SteeringSource, key: &str) -> bool {
+    match store.get_f32(key) {
+        Some(zero) => {
+            info!("Loaded steering zero: {:.1}", zero);
+            source.set_zero(zero);
+            true
+        }
+        None => {
+            source.set_zero(0.0);
+            false
+        }
+    }
+}
+
+/// The response of a joystick axis, for the axes that have one.
+fn stick_axis(stick: &mut StickResponse, axis: Axis) -> Option<&mut AxisResponse> {
+    match axis {
+        Axis::X => Some(&mut stick.x),
+        Axis::Y => Some(&mut stick.y),
+        _ => None,
+    }
+}
+
+/// The stored joystick calibration, if there is a usable one.
+fn load_joystick_calibration(store: &Store) -> Option<JoystickCalibration> {
+    let range = |keys: [&str; 3]| {
+        let [min, mid, max] = store.get_u16s(keys)?;
+        Some(AxisRange { min, mid, max })
+    };
+    let calibration = JoystickCalibration {
+        x: range(JOYSTICK_KEYS[0])?,
+        y: range(JOYSTICK_KEYS[1])?,
+    };
+    calibration.valid().then_some(calibration)
+}
+
+fn save_joystick_calibration(
+    store: &Store,
+    calibration: JoystickCalibration,
+) -> anyhow::Result<()> {
+    for (keys, range) in JOYSTICK_KEYS
+        .into_iter()
+        .zip([calibration.x, calibration.y])
+    {
+        store.set_u16s(keys, [range.min, range.mid, range.max])?;
+    }
+    Ok(())
+}
 
 fn main() -> anyhow::Result<()> {
     esp_idf_hal::sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
 
     let peripherals = Peripherals::take()?;
+    let store = Store::new()?;
 
     let mut timer00 = TimerDriver::new(peripherals.timer00, &TimerConfig::new())?;
     let mut timer01 = TimerDriver::new(peripherals.timer01, &TimerConfig::new())?;
     let mut timer10 = TimerDriver::new(peripherals.timer10, &TimerConfig::new())?;
-    let timer11 = TimerDriver::new(peripherals.timer11, &TimerConfig::new())?;
+    let mut timer11 = TimerDriver::new(peripherals.timer11, &TimerConfig::new())?;
 
-    let i2c = peripherals.i2c0;
-    let sda = peripherals.pins.gpio21;
-    let scl = peripherals.pins.gpio22;
+    let source_kind = store
+        .get_u8(SOURCE_KEY)
+        .and_then(SourceKind::from_u8)
+        .unwrap_or(SourceKind::Imu);
+    let invert = store.get_u8(INVERT_KEY).unwrap_or(0) != 0;
+    info!("Using steering source: {:?}", source_kind);
 
     let i2c_config = I2cConfig::new().baudrate(Hertz(400_000));
-    let i2c = I2cDriver::new(i2c, sda, scl, &i2c_config)?;
-    let mut mpu = MpuSensor::new(i2c)?;
+    let mut source: Box<dyn SteeringSource> = match source_kind {
+        SourceKind::Imu => {
+            let mut imu_config = ImuConfig::default();
+            if let Some(rate) = store.get_u16(IMU_RATE_KEY) {
+                imu_config.sample_rate = rate;
+            }
+            if let Some(dlpf) = store.get_u8(IMU_DLPF_KEY).and_then(Dlpf::from_u8) {
+                imu_config.dlpf = dlpf;
+            }
+            info!("IMU config: {:?}", imu_config);
+            let bus = I2cBus::new(
+                peripherals.i2c0,
+                peripherals.pins.gpio21.downgrade(),
+                peripherals.pins.gpio22.downgrade(),
+                i2c_config,
+            );
+            Box::new(MpuSensor::new(bus, imu_config)?)
+        }
+        SourceKind::As5600 => {
+            let i2c = I2cDriver::new(
+                peripherals.i2c0,
+                peripherals.pins.gpio21,
+                peripherals.pins.gpio22,
+                &i2c_config,
+            )?;
+            Box::new(As5600::new(i2c, invert)?)
+        }
+        SourceKind::As5048 => {
+            // sclk: 22 (SCL), mosi: 21 (SDA), miso: 36 (VP), cs: 0
+            let spi = SpiDeviceDriver::new_single(
+                peripherals.spi2,
+                peripherals.pins.gpio22,
+                peripherals.pins.gpio21,
+                Some(peripherals.pins.gpio36),
+                Some(peripherals.pins.gpio0),
+                &SpiDriverConfig::new(),
+                &SpiConfig::new()
+                    .baudrate(Hertz(1_000_000))
+                    .data_mode(MODE_1),
+            )?;
+            Box::new(As5048::new(spi, invert)?)
+        }
+    };
+    let mount = match store.get_u8(MOUNT_KEY) {
+        Some(MOUNT_CAPTURED) => store
+            .get_f32s(MOUNT_QUAT_KEYS)
+            .and_then(Mount::from_quaternion),
+        Some(index) => Mount::axis_aligned(index),
+        None => None,
+    };
+    if let Some(mount) = mount {
+        info!("Loaded IMU mount: {:?}", mount);
+        source.set_mount(mount);
+    }
+    let mut mount_calibration = MountCalibration::new();
+    let health = Cell::new(source.health());
+
+    for (sensor, (offset_keys, slope_keys)) in GYRO_OFFSET_KEYS
+        .into_iter()
+        .zip(GYRO_SLOPE_KEYS)
+        .enumerate()
+    {
+        if let (Some(offset), Some(slope)) =
+            (store.get_f32s(offset_keys), store.get_f32s(slope_keys))
+        {
+            let model = GyroTempModel { offset, slope };
+            info!("Loaded gyro {} temperature model: {:?}", sensor, model);
+            source.set_gyro_temp_model(sensor, model);
+        }
+    }
+
+    let mut profile_index = match store.get_u8(PROFILE_KEY) {
+        Some(index) if (index as usize) < PROFILES.len() => index as usize,
+        _ => DEFAULT_PROFILE,
+    };
+    let mut profile = PROFILES[profile_index];
+    info!("Using steering profile: {}", profile.name);
+    source.set_mode(profile.mode);
+
+    let mut zero_key = zero_key_for(source_kind, profile.mode);
+    let mut zeroed = load_zero(&store, source.as_mut(), zero_key);
+    let recenter = Cell::new(false);
+    let tilt_throttle: Cell<Option<f32>> = Cell::new(None);
+
+    let mut gesture_config = GestureConfig::default();
+    source.set_gesture_config(gesture_config);
+    let mut gesture_actions = DEFAULT_GESTURE_ACTIONS;
+    let virtual_buttons = RefCell::new(VirtualButtons::new());
+    let key_timings = Cell::new(KeyTimings::default());
+    let layer_mode = Cell::new(
+        store
+            .get_u8(LAYER_MODE_KEY)
+            .and_then(LayerMode::from_u8)
+            .unwrap_or(LayerMode::Momentary),
+    );
+    info!("Keypad layer mode: {:?}", layer_mode.get());
+    let layer_toggled = Cell::new(false);
+    let calibrate_joystick = Cell::new(false);
+
+    let mut policy = SourcePolicy::new(
+        store
+            .get_u8(FALLBACK_KEY)
+            .and_then(FallbackKind::from_u8)
+            .unwrap_or(FallbackKind::Joystick),
+    );
+    info!("Steering fallback: {:?}", policy.fallback());
+    let joystick_steering = Cell::new(0.0);
+    let key_steering = Cell::new(0.0);
+    // Whether the fallback keys steer, so they are not reported as buttons
+    let keys_steer = Cell::new(false);
+
+    let filters: RefCell<[Chain; Axis::COUNT]> = RefCell::new(Default::default());
+    filters.borrow_mut()[Axis::Steering as usize].configure(profile.smoothing);
 
     let mut led = Switch::new(peripherals.pins.gpio2, false)?;
-    let mut motor = Switch::new(peripherals.pins.gpio15, false)?;
+    let motor = Switch::new(peripherals.pins.gpio15, false)?;
+    let mut haptic = Haptic::new(motor)?;
 
-    let mut gear_drive = Button::new(peripherals.pins.gpio18, false)?;
-    let mut gear_reverse = Button::new(peripherals.pins.gpio19, false)?;
-    let mut gear_left = Button::new(peripherals.pins.gpio12, false)?;
-    let mut gear_right = Button::new(peripherals.pins.gpio13, false)?;
+    let mut gear_drive = Button::new(switch_pin(peripherals.pins.gpio18, false)?, false);
+    let mut gear_reverse = Button::new(switch_pin(peripherals.pins.gpio19, false)?, false);
+    let mut gear_left = Button::new(switch_pin(peripherals.pins.gpio12, false)?, false);
+    let mut gear_right = Button::new(switch_pin(peripherals.pins.gpio13, false)?, false);
+    gear_drive.set_debounce(GEAR_DEBOUNCE_TIME);
+    gear_reverse.set_debounce(GEAR_DEBOUNCE_TIME);
+    gear_left.set_debounce(GEAR_DEBOUNCE_TIME);
+    gear_right.set_debounce(GEAR_DEBOUNCE_TIME);
 
     let adc = AdcDriver::new(peripherals.adc1)?;
 
+    let joystick_calibration = load_joystick_calibration(&store);
+    match joystick_calibration {
+        Some(calibration) => info!("Joystick calibration: {:?}", calibration),
+        None => info!("Joystick not calibrated, capturing the centre"),
+    }
+    let axis_response = AxisResponse {
+        deadzone: JOYSTICK_DEADZONE,
+        ..Default::default()
+    };
+    let stick_response = Cell::new(StickResponse {
+        mode: DeadzoneMode::Radial,
+        x: axis_response,
+        y: axis_response,
+    });
     let mut joystick = match Joystick::new(
-        &adc,
-        &adc,
-        peripherals.pins.gpio34,
-        peripherals.pins.gpio35,
-        peripherals.pins.gpio23,
-        1600,
+        EspAnalog::new(&adc, peripherals.pins.gpio34)?,
+        EspAnalog::new(&adc, peripherals.pins.gpio35)?,
+        switch_pin(peripherals.pins.gpio23, false)?,
+        joystick_calibration,
+        stick_response.get(),
         AX_MIN,
         AX_MAX,
     ) {
@@ -78,40 +393,31 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
-    let mut pedal = match Pedal::new(
-        &adc,
-        &adc,
-        peripherals.pins.gpio32,
-        peripherals.pins.gpio33,
+    let mut pedal = Pedal::new(
+        EspAnalog::new(&adc, peripherals.pins.gpio32)?,
+        EspAnalog::new(&adc, peripherals.pins.gpio33)?,
         700,
         SM_MIN,
         SM_MAX,
-    ) {
-        Ok(pedal) => {
-            info!("Pedal initialized successfully");
-            pedal
-        }
-        Err(e) => {
-            warn!("Failed to initialize pedal: {:?}", e);
-            return Err(e);
-        }
-    };
+    );
+    info!("Pedal initialized successfully");
 
     led.off()?;
-    motor.off()?;
+    let mut status_led = StatusLed::new(led);
 
     let ms00 = timer00.tick_hz() / 1000;
     let ms01 = timer01.tick_hz() / 1000;
     let ms10 = timer10.tick_hz() / 1000;
+    let us11 = timer11.tick_hz() / 1_000_000;
 
     // row: 26 27 14 12
     // col: 4 16 17 5
-    let mut keypad = match Keypad::new(
+    let keypad = match Keypad::new(
         [
-            PinDriver::input(peripherals.pins.gpio4.downgrade())?,
-            PinDriver::input(peripherals.pins.gpio16.downgrade())?,
-            PinDriver::input(peripherals.pins.gpio17.downgrade())?,
-            PinDriver::input(peripherals.pins.gpio5.downgrade())?,
+            switch_pin(peripherals.pins.gpio4.downgrade(), false)?,
+            switch_pin(peripherals.pins.gpio16.downgrade(), false)?,
+            switch_pin(peripherals.pins.gpio17.downgrade(), false)?,
+            switch_pin(peripherals.pins.gpio5.downgrade(), false)?,
         ],
         [
             PinDriver::output(peripherals.pins.gpio25.downgrade_output())?,
@@ -119,7 +425,9 @@ fn main() -> anyhow::Result<()> {
             PinDriver::output(peripherals.pins.gpio27.downgrade_output())?,
             PinDriver::output(peripherals.pins.gpio14.downgrade_output())?,
         ],
-        timer11,
+        Ets,
+        KEYPAD_SETTLE_US,
+        KEYPAD_HAS_DIODES,
     ) {
         Ok(keypad) => {
             info!("Keypad initialized successfully");
@@ -131,6 +439,8 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
+    let keypad = RefCell::new(keypad);
+
     let ble_steering = match Steering::new() {
         Ok(steering) => {
             info!("BLE steering initialized successfully");
@@ -141,51 +451,391 @@ fn main() -> anyhow::Result<()> {
             return Err(e);
         }
     };
+    ble_steering.set_diagnostics(&health.get().diagnostics());
 
     block_on(async {
         join!(
             async {
+                let mut past_lock = false;
+                let mut filtered_at = Instant::now();
+                let mut polled_at = Instant::now();
                 loop {
                     timer00.delay(10 * ms00).await.expect("Timer delay failed");
-                    match mpu.roll() {
-                        Some(roll) => {
-                            let roll = roll.clamp(-STEERING_ROTATION_ANGLE / 2.0, STEERING_ROTATION_ANGLE / 2.0);
-                            let report_ratio =
-                                (SM_MAX - SM_MIN) as f32 / STEERING_ROTATION_ANGLE;
-                            let roll = (roll + STEERING_ROTATION_ANGLE / 2.0) * report_ratio;
-                            ble_steering.set_steering(roll as i16);
+                    let gesture_command = source.take_gesture().and_then(|gesture| {
+                        info!("Gesture: {:?}", gesture);
+                        match gesture_actions[gesture as usize] {
+                            GestureAction::None => None,
+                            GestureAction::Button(index) => {
+                                virtual_buttons
+                                    .borrow_mut()
+                                    .press(index, GESTURE_PRESS_TIME);
+                                None
+                            }
+                            GestureAction::Recenter => Some(Command::Recenter),
+                            GestureAction::NextProfile => Some(Command::SelectProfile(
+                                ((profile_index + 1) % PROFILES.len()) as u8,
+                            )),
                         }
-                        None => {}
+                    });
+                    let commands = gesture_command
+                        .into_iter()
+                        .chain(std::iter::from_fn(|| ble_steering.take_command()));
+                    for command in commands {
+                        match command {
+                            Command::Recenter => recenter.set(true),
+                            Command::SelectProfile(index) => match PROFILES.get(index as usize) {
+                                Some(selected) => {
+                                    if selected.mode != profile.mode {
+                                        source.set_mode(selected.mode);
+                                        zero_key = zero_key_for(source_kind, selected.mode);
+                                        zeroed = load_zero(&store, source.as_mut(), zero_key);
+                                    }
+                                    profile_index = index as usize;
+                                    profile = *selected;
+                                    info!("Switched to steering profile: {}", profile.name);
+                                    filters.borrow_mut()[Axis::Steering as usize]
+                                        .configure(profile.smoothing);
+                                    if let Err(e) = store.set_u8(PROFILE_KEY, index) {
+                                        warn!("Failed to store profile: {:?}", e);
+                                    }
+                                }
+                                None => warn!("Unknown steering profile: {}", index),
+                            },
+                            Command::SelectSource(kind) => match SourceKind::from_u8(kind) {
+                                Some(kind) => {
+                                    info!(
+                                        "Steering source {:?} selected, applies after reboot",
+                                        kind
+                                    );
+                                    if let Err(e) = store.set_u8(SOURCE_KEY, kind as u8) {
+                                        warn!("Failed to store steering source: {:?}", e);
+                                    }
+                                }
+                                None => warn!("Unknown steering source: {}", kind),
+                            },
+                            Command::InvertSource(invert) => {
+                                info!("Steering inversion set to {}, applies after reboot", invert);
+                                if let Err(e) = store.set_u8(INVERT_KEY, invert as u8) {
+                                    warn!("Failed to store steering inversion: {:?}", e);
+                                }
+                            }
+                            Command::SetSampleRate(rate) => {
+                                info!("IMU sample rate set to {} Hz, applies after reboot", rate);
+                                if let Err(e) = store.set_u16(IMU_RATE_KEY, rate) {
+                                    warn!("Failed to store IMU sample rate: {:?}", e);
+                                }
+                            }
+                            Command::SetDlpf(dlpf) => match Dlpf::from_u8(dlpf) {
+                                Some(dlpf) => {
+                                    info!("IMU filter set to {:?}, applies after reboot", dlpf);
+                                    if let Err(e) = store.set_u8(IMU_DLPF_KEY, dlpf as u8) {
+                                        warn!("Failed to store IMU filter: {:?}", e);
+                                    }
+                                }
+                                None => warn!("Unknown IMU filter: {}", dlpf),
+                            },
+                            Command::SetMount(index) => match Mount::axis_aligned(index) {
+                                Some(mount) => {
+                                    source.set_mount(mount);
+                                    info!("IMU mount set to {}, recenter to finish", index);
+                                    if let Err(e) = store.set_u8(MOUNT_KEY, index) {
+                                        warn!("Failed to store IMU mount: {:?}", e);
+                                    }
+                                }
+                                None => warn!("Unknown IMU mount: {}", index),
+                            },
+                            Command::CalibrateMount => match source.gravity() {
+                                None => warn!("Steering source has no mount to calibrate"),
+                                Some(_) if source.still_for() < MOUNT_STILL_TIME => {
+                                    warn!("Hold the wheel still to calibrate the mount")
+                                }
+                                Some(gravity) => match mount_calibration.capture(gravity) {
+                                    MountCapture::Centre => info!(
+                                        "Mount calibration: centre captured, turn the wheel and calibrate again"
+                                    ),
+                                    MountCapture::TurnTooSmall => warn!(
+                                        "Mount calibration: wheel not turned far enough, start again"
+                                    ),
+                                    MountCapture::Done(mount) => {
+                                        info!("Mount calibrated: {:?}", mount);
+                                        source.set_mount(mount);
+                                        // The captured centre is level by construction, but
+                                        // that says nothing about where yaw is straight ahead
+                                        let level = profile.mode != AxisMode::Yaw;
+                                        if level {
+                                            source.set_zero(0.0);
+                                            zeroed = true;
+                                            filters.borrow_mut()[Axis::Steering as usize].reset();
+                                        } else {
+                                            info!("Recenter to finish");
+                                        }
+                                        let save = || -> anyhow::Result<()> {
+                                            store.set_f32s(MOUNT_QUAT_KEYS, mount.quaternion())?;
+                                            store.set_u8(MOUNT_KEY, MOUNT_CAPTURED)?;
+                                            if level {
+                                                store.set_f32(zero_key, 0.0)?;
+                                            }
+                                            Ok(())
+                                        };
+                                        if let Err(e) = save() {
+                                            warn!("Failed to store IMU mount: {:?}", e);
+                                        }
+                                    }
+                                },
+                            },
+                            Command::SetGestureAction { gesture, action } => {
+                                info!("{:?} gesture set to {:?}", gesture, action);
+                                gesture_actions[gesture as usize] = action;
+                            }
+                            Command::SetGestureThreshold { gesture, threshold } => {
+                                info!("{:?} gesture threshold set to {}", gesture, threshold);
+                                gesture_config.set_threshold(gesture, threshold);
+                                source.set_gesture_config(gesture_config);
+                            }
+                            Command::SetKeyTimings(timings) => {
+                                info!("Key timings set to {:?}", timings);
+                                key_timings.set(timings);
+                            }
+                            Command::SetLayerMode(mode) => match LayerMode::from_u8(mode) {
+                                Some(mode) => {
+                                    info!("Keypad layer mode set to {:?}", mode);
+                                    layer_mode.set(mode);
+                                    if let Err(e) = store.set_u8(LAYER_MODE_KEY, mode as u8) {
+                                        warn!("Failed to store keypad layer mode: {:?}", e);
+                                    }
+                                }
+                                None => warn!("Unknown keypad layer mode: {}", mode),
+                            },
+                            Command::CalibrateJoystick => calibrate_joystick.set(true),
+                            Command::SetDeadzoneMode(mode) => match DeadzoneMode::from_u8(mode) {
+                                Some(mode) => {
+                                    info!("Joystick deadzone mode set to {:?}", mode);
+                                    let mut response = stick_response.get();
+                                    response.mode = mode;
+                                    stick_response.set(response);
+                                }
+                                None => warn!("Unknown joystick deadzone mode: {}", mode),
+                            },
+                            Command::SetAxisDeadzones {
+                                axis,
+                                deadzone,
+                                outer,
+                                anti_deadzone,
+                            } => {
+                                let mut stick = stick_response.get();
+                                match stick_axis(&mut stick, axis) {
+                                    Some(response) => {
+                                        response.deadzone = deadzone;
+                                        response.outer = outer;
+                                        response.anti_deadzone = anti_deadzone;
+                                        info!("{:?} response set to {:?}", axis, response);
+                                        stick_response.set(stick);
+                                    }
+                                    None => warn!("{:?} axis has no response curve", axis),
+                                }
+                            }
+                            Command::SetAxisCurve { axis, curve } => {
+                                let mut stick = stick_response.get();
+                                match stick_axis(&mut stick, axis) {
+                                    Some(response) => {
+                                        response.curve = curve;
+                                        info!("{:?} response set to {:?}", axis, response);
+                                        stick_response.set(stick);
+                                    }
+                                    None => warn!("{:?} axis has no response curve", axis),
+                                }
+                            }
+                            Command::SetFallback(kind) => match FallbackKind::from_u8(kind) {
+                                Some(kind) => {
+                                    info!("Steering fallback set to {:?}", kind);
+                                    policy.set_fallback(kind);
+                                    if let Err(e) = store.set_u8(FALLBACK_KEY, kind as u8) {
+                                        warn!("Failed to store steering fallback: {:?}", e);
+                                    }
+                                }
+                                None => warn!("Unknown steering fallback: {}", kind),
+                            },
+                            Command::SetFilter {
+                                axis,
+                                stage,
+                                config,
+                            } => {
+                                let mut filters = filters.borrow_mut();
+                                if filters[axis as usize].set_stage(stage as usize, config) {
+                                    info!(
+                                        "{:?} filter: {:?}",
+                                        axis,
+                                        filters[axis as usize].configs()
+                                    );
+                                } else {
+                                    warn!("Invalid {:?} filter stage: {}", axis, stage);
+                                }
+                            }
+                        }
+                    }
+                    let angle = source.angle();
+                    tilt_throttle.set(source.pitch().and_then(|pitch| profile.throttle(pitch)));
+                    let current = source.health();
+                    if current != health.get() {
+                        health.set(current);
+                        ble_steering.set_diagnostics(&current.diagnostics());
+                    }
+                    if let Some((sensor, model)) = source.learned_gyro_temp_model() {
+                        let save = || -> anyhow::Result<()> {
+                            store.set_f32s(GYRO_OFFSET_KEYS[sensor], model.offset)?;
+                            store.set_f32s(GYRO_SLOPE_KEYS[sensor], model.slope)
+                        };
+                        match save() {
+                            Ok(_) => info!("Stored gyro {} temperature model: {:?}", sensor, model),
+                            Err(e) => warn!("Failed to store gyro temperature model: {:?}", e),
+                        }
+                    }
+                    if angle.is_some() {
+                        if !zeroed && source.still_for() >= BOOT_STILL_TIME {
+                            recenter.set(true);
+                        }
+                        if recenter.take() {
+                            let zero = source.recenter();
+                            zeroed = true;
+                            filters.borrow_mut()[Axis::Steering as usize].reset();
+                            match store.set_f32(zero_key, zero) {
+                                Ok(_) => info!("Steering recentered at {:.1}", zero),
+                                Err(e) => warn!("Failed to store steering zero: {:?}", e),
+                            }
+                        }
+                    }
+                    let was_active = policy.active();
+                    let active = policy.update(
+                        angle.is_some(),
+                        source.degraded(),
+                        polled_at.elapsed().as_secs_f32(),
+                    );
+                    polled_at = Instant::now();
+                    if active != was_active {
+                        info!("Steering with {:?}", active);
+                        // The filter state is from before the sensor was lost
+                        filters.borrow_mut()[Axis::Steering as usize].reset();
+                        filtered_at = Instant::now();
+                    }
+                    keys_steer
+                        .set(active == Active::Fallback && policy.fallback() == FallbackKind::Keys);
+                    let report_ratio = (SM_MAX - SM_MIN) as f32 / 2.0;
+                    match (active, angle) {
+                        (Active::Fallback, _) => {
+                            let steering = match policy.fallback() {
+                                FallbackKind::Joystick => joystick_steering.get(),
+                                FallbackKind::Keys => key_steering.get(),
+                                FallbackKind::None => 0.0,
+                            };
+                            past_lock = false;
+                            let steering = (steering + 1.0) * report_ratio;
+                            ble_steering.set_steering(SM_MIN + steering as i16);
+                        }
+                        (Active::Sensor, Some(angle)) => {
+                            let dt = filtered_at.elapsed().as_secs_f32();
+                            filtered_at = Instant::now();
+                            let angle =
+                                filters.borrow_mut()[Axis::Steering as usize].update(angle, dt);
+                            if profile.past_lock(angle) && !past_lock {
+                                if let Err(e) = haptic.pulse(LOCK_BUZZ_TIME) {
+                                    warn!("Error pulsing motor: {:?}", e);
+                                }
+                            }
+                            past_lock = profile.past_lock(angle);
+                            let steering = (profile.steering(angle) + 1.0) * report_ratio;
+                            ble_steering.set_steering(SM_MIN + steering as i16);
+                        }
+                        (Active::Sensor, None) => {
+                            if source.degraded() && profile.hold == Hold::Centre {
+                                ble_steering.set_steering(SM_MIN + (SM_MAX - SM_MIN) / 2);
+                            }
+                        }
+                    }
+                    if layer_toggled.take() {
+                        if let Err(e) = haptic.pulse(LAYER_BUZZ_TIME) {
+                            warn!("Error pulsing motor: {:?}", e);
+                        }
+                    }
+                    if let Err(e) = haptic.update() {
+                        warn!("Error updating motor: {:?}", e);
                     }
                 }
             },
             async {
                 loop {
-                    if ble_steering.connected() {
-                        ble_steering.send_report();
-                        timer10.delay(7 * ms10).await.expect("Timer delay failed");
+                    let connected = ble_steering.connected();
+                    let health = health.get();
+                    let status = if health.failed() {
+                        Status::Fault
+                    } else if health.flags.contains(HealthFlags::DEGRADED) {
+                        Status::Degraded
+                    } else if connected {
+                        Status::Connected
                     } else {
-                        let _ = led.off();
-                        timer10.delay(500 * ms10).await.expect("Timer delay failed");
-                        let _ = led.on();
-                        timer10.delay(500 * ms10).await.expect("Timer delay failed");
+                        Status::Advertising
+                    };
+                    let _ = status_led.update(status);
+                    if connected {
+                        ble_steering.send_report();
                     }
+                    timer10.delay(7 * ms10).await.expect("Timer delay failed");
                 }
             },
             async {
-                loop {
-                    let mut states: u32 = 0;
-                    match keypad.scan(5).await {
-                        Ok(_) => {
-                            states |= keypad.states() as u32;
+                let mut recenter_sent = false;
+                let mut filtered_at = Instant::now();
+                let mut key_axis = KeyAxis::new();
+                let mut key_actions =
+                    KeyActions::new(key_timings.get(), &KEY_BINDINGS, &KEY_CHORDS);
+                let mut layer = ShiftLayer::new(layer_mode.get());
+                // Keys with actions press their buttons through them only, and
+                // a shift key presses none
+                let mut keymap = KEYMAP;
+                let mut shifted_keymap = SHIFTED_KEYMAP;
+                for (row, (bits, shifted_bits)) in
+                    keymap.iter_mut().zip(shifted_keymap.iter_mut()).enumerate()
+                {
+                    for (col, (bit, shifted_bit)) in
+                        bits.iter_mut().zip(shifted_bits.iter_mut()).enumerate()
+                    {
+                        let shift = SHIFT_INPUT == ShiftInput::Key(row, col);
+                        if shift || key_actions.is_bound((row, col)) {
+                            *bit = None;
                         }
-                        Err(e) => {
-                            warn!("Error scanning keypad: {:?}", e);
+                        if shift {
+                            *shifted_bit = None;
                         }
                     }
+                }
+                loop {
+                    let dt = filtered_at.elapsed().as_secs_f32();
+                    filtered_at = Instant::now();
+                    let mut states: u64 = 0;
+                    if calibrate_joystick.take() {
+                        match joystick.calibrate() {
+                            Ok(JoystickCapture::Sweep) => info!(
+                                "Joystick calibration: move the stick all around, then release it and calibrate again"
+                            ),
+                            Ok(JoystickCapture::RangeTooSmall) => warn!(
+                                "Joystick calibration: stick not moved far enough, start again"
+                            ),
+                            Ok(JoystickCapture::Done(calibration)) => {
+                                info!("Joystick calibrated: {:?}", calibration);
+                                if let Err(e) = save_joystick_calibration(&store, calibration) {
+                                    warn!("Failed to store joystick calibration: {:?}", e);
+                                }
+                            }
+                            Err(e) => warn!("Error calibrating joystick: {:?}", e),
+                        }
+                    }
+                    joystick.set_response(stick_response.get());
                     match joystick.read() {
                         Ok((x, y, pressed)) => {
-                            ble_steering.set_axes(x, y);
+                            let mut filters = filters.borrow_mut();
+                            let x = filters[Axis::X as usize].update(x as f32, dt);
+                            let y = filters[Axis::Y as usize].update(y as f32, dt);
+                            ble_steering.set_axes(x as i16, y as i16);
+                            joystick_steering.set((x / AX_MAX as f32).clamp(-1.0, 1.0));
                             if pressed {
                                 states |= 1 << 16; // Button pressed
                             } else {
@@ -198,7 +848,16 @@ fn main() -> anyhow::Result<()> {
                     }
                     match pedal.read() {
                         Ok((accelerator, brake)) => {
-                            ble_steering.set_pedals(accelerator, brake);
+                            let mut accelerator = accelerator as f32;
+                            if let Some(throttle) = tilt_throttle.get() {
+                                let tilt = SM_MIN as f32 + throttle * (SM_MAX - SM_MIN) as f32;
+                                accelerator = accelerator.max(tilt);
+                            }
+                            let mut filters = filters.borrow_mut();
+                            let accelerator =
+                                filters[Axis::Accelerator as usize].update(accelerator, dt);
+                            let brake = filters[Axis::Brake as usize].update(brake as f32, dt);
+                            ble_steering.set_pedals(accelerator as i16, brake as i16);
                         }
                         Err(e) => {
                             warn!("Error reading pedal: {:?}", e);
@@ -252,9 +911,86 @@ fn main() -> anyhow::Result<()> {
                             warn!("Error reading gear right: {:?}", e);
                         }
                     }
-                    ble_steering.set_buttons(states);
+                    let shift_held = match SHIFT_INPUT {
+                        ShiftInput::Key(row, col) => keypad
+                            .borrow()
+                            .key_state(row, col)
+                            .contains(KeyState::PRESSED),
+                        ShiftInput::Button(bit) => {
+                            let held = states & 1 << bit != 0;
+                            states &= !(1 << bit);
+                            held
+                        }
+                    };
+                    let was_shifted = layer.shifted();
+                    if layer.mode() != layer_mode.get() {
+                        layer.set_mode(layer_mode.get());
+                    }
+                    let shifted = layer.update(shift_held);
+                    if shifted != was_shifted {
+                        info!("Keypad bank: {}", if shifted { 2 } else { 1 });
+                        // Actions half done in one bank do not carry over
+                        key_actions =
+                            KeyActions::new(key_timings.get(), &KEY_BINDINGS, &KEY_CHORDS);
+                        if layer.mode() == LayerMode::Toggle {
+                            layer_toggled.set(true);
+                        }
+                    }
+                    let keymap = if shifted { &shifted_keymap } else { &keymap };
+                    key_actions.set_timings(key_timings.get());
+                    key_actions.update(dt);
+                    for (row, col, state) in keypad.borrow_mut().events() {
+                        if !shifted {
+                            key_actions.key((row, col), state.contains(KeyState::JUST_PRESS));
+                        }
+                    }
+                    for button in key_actions.take_buttons() {
+                        virtual_buttons
+                            .borrow_mut()
+                            .press(button, KEY_ACTION_PRESS_TIME);
+                    }
+                    states |= keypad.borrow().buttons(keymap);
+                    if keys_steer.get() {
+                        let keypad = keypad.borrow();
+                        let pressed = |(row, col): (usize, usize)| {
+                            keypad.key_state(row, col).contains(KeyState::PRESSED)
+                        };
+                        let left = pressed(FALLBACK_LEFT_KEY);
+                        let right = pressed(FALLBACK_RIGHT_KEY);
+                        key_steering.set(key_axis.update(left, right, dt));
+                        for (row, col) in [FALLBACK_LEFT_KEY, FALLBACK_RIGHT_KEY] {
+                            if let Some(bit) = keymap[row][col] {
+                                states &= !(1 << bit);
+                            }
+                        }
+                    } else {
+                        key_axis = KeyAxis::new();
+                        key_steering.set(0.0);
+                    }
+                    match gear_left.held_for().zip(gear_right.held_for()) {
+                        Some((left, right)) if left.min(right) >= RECENTER_HOLD_TIME => {
+                            if !recenter_sent {
+                                recenter.set(true);
+                                recenter_sent = true;
+                            }
+                        }
+                        Some(_) => {}
+                        None => recenter_sent = false,
+                    }
+                    ble_steering.set_buttons(states | virtual_buttons.borrow_mut().states());
                     timer01.delay(5 * ms01).await.expect("Timer delay failed");
                 }
+            },
+            async {
+                loop {
+                    if let Err(e) = keypad.borrow_mut().scan_row() {
+                        warn!("Error scanning keypad: {:?}", e);
+                    }
+                    timer11
+                        .delay(KEYPAD_ROW_US * us11)
+                        .await
+                        .expect("Timer delay failed");
+                }
             }
         );
         Ok(())
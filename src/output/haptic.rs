@@ -0,0 +1,36 @@
+use super::Switch;
+use esp_idf_hal::gpio::OutputPin;
+use std::time::{Duration, Instant};
+
+/// Drives the vibration motor in timed pulses.
+pub struct Haptic<'a, T: OutputPin> {
+    motor: Switch<'a, T>,
+    until: Option<Instant>,
+}
+
+impl<'a, T: OutputPin> Haptic<'a, T> {
+    pub fn new(mut motor: Switch<'a, T>) -> anyhow::Result<Self> {
+        motor.off()?;
+        Ok(Self { motor, until: None })
+    }
+
+    /// Starts the motor, or extends a running pulse, for `duration`.
+    pub fn pulse(&mut self, duration: Duration) -> anyhow::Result<()> {
+        self.motor.on()?;
+        self.until = Some(Instant::now() + duration);
+        Ok(())
+    }
+
+    /// Stops the motor once the current pulse is over.
+    ///
+    /// Must be called periodically.
+    pub fn update(&mut self) -> anyhow::Result<()> {
+        if let Some(until) = self.until {
+            if Instant::now() >= until {
+                self.motor.off()?;
+                self.until = None;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,2 +1,8 @@
 mod switch;
-pub use switch::*;
\ No newline at end of file
+pub use switch::*;
+
+mod haptic;
+pub use haptic::*;
+
+mod status_led;
+pub use status_led::*;
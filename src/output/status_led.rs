@@ -0,0 +1,65 @@
+use super::Switch;
+use esp_idf_hal::gpio::OutputPin;
+use std::time::Instant;
+
+/// What the status LED shows, in order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// A sensor failed its checks: fast blinking.
+    Fault,
+    /// A sensor is lost and being recovered: double blinks.
+    Degraded,
+    /// Waiting for a host: slow blinking.
+    Advertising,
+    /// Connected: steady on.
+    Connected,
+}
+
+impl Status {
+    /// Blink period and the on intervals within it, in ms.
+    fn pattern(self) -> (u128, &'static [(u128, u128)]) {
+        match self {
+            Status::Fault => (200, &[(0, 100)]),
+            Status::Degraded => (1000, &[(0, 100), (200, 300)]),
+            Status::Advertising => (1000, &[(0, 500)]),
+            Status::Connected => (1000, &[(0, 1000)]),
+        }
+    }
+}
+
+/// Shows the device status on an LED, without blocking.
+pub struct StatusLed<'a, T: OutputPin> {
+    led: Switch<'a, T>,
+    start: Instant,
+    lit: Option<bool>,
+}
+
+impl<'a, T: OutputPin> StatusLed<'a, T> {
+    pub fn new(led: Switch<'a, T>) -> Self {
+        Self {
+            led,
+            start: Instant::now(),
+            lit: None,
+        }
+    }
+
+    /// Sets the LED for `status` at the current point of its pattern.
+    ///
+    /// Must be called periodically, at least every few tens of ms.
+    pub fn update(&mut self, status: Status) -> anyhow::Result<()> {
+        let (period, intervals) = status.pattern();
+        let phase = self.start.elapsed().as_millis() % period;
+        let lit = intervals
+            .iter()
+            .any(|&(on, off)| (on..off).contains(&phase));
+        if self.lit != Some(lit) {
+            if lit {
+                self.led.on()?;
+            } else {
+                self.led.off()?;
+            }
+            self.lit = Some(lit);
+        }
+        Ok(())
+    }
+}
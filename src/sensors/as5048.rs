@@ -0,0 +1,125 @@
+use super::{SteeringSource, Supervisor};
+use esp32_ble_steering_rs::fusion::{wrap_angle, TurnCounter};
+use esp_idf_hal::spi::{SpiDeviceDriver, SpiDriver};
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+// Read command for the angle register (0x3FFF) with the read and parity bits set
+const CMD_READ_ANGLE: u16 = 0xFFFF;
+// Read command for the error register (0x0001), which also clears it
+const CMD_CLEAR_ERROR: u16 = 0x4001;
+const FRAME_ERROR: u16 = 1 << 14;
+const COUNTS_PER_TURN: f32 = 16384.0;
+/// Angle change (degrees) per reading below which the wheel counts as held still.
+const STILL_STEP: f32 = 0.2;
+
+/// AS5048A 14-bit magnetic angle sensor on SPI.
+///
+/// The sensor answers each command in the following frame, so every
+/// reading returns the angle sampled at the previous call.
+pub struct As5048<'a> {
+    spi: SpiDeviceDriver<'a, SpiDriver<'a>>,
+    invert: bool,
+    turns: TurnCounter,
+    still_since: Instant,
+    supervisor: Supervisor,
+}
+
+impl<'a> As5048<'a> {
+    pub fn new(spi: SpiDeviceDriver<'a, SpiDriver<'a>>, invert: bool) -> anyhow::Result<Self> {
+        let mut res = Self {
+            spi,
+            invert,
+            turns: TurnCounter::new(),
+            still_since: Instant::now(),
+            supervisor: Supervisor::new(),
+        };
+        res.reset()?;
+        Ok(res)
+    }
+
+    /// Clears any error left from power-up or a glitch, and queues the
+    /// next angle read.
+    fn reset(&mut self) -> anyhow::Result<()> {
+        self.transfer(CMD_CLEAR_ERROR)?;
+        self.transfer(CMD_READ_ANGLE)?;
+        Ok(())
+    }
+
+    fn transfer(&mut self, command: u16) -> anyhow::Result<u16> {
+        let mut read = [0u8; 2];
+        self.spi.transfer(&mut read, &command.to_be_bytes())?;
+        Ok(u16::from_be_bytes(read))
+    }
+
+    /// Reads the angle within a single turn, in degrees.
+    fn raw_angle(&mut self) -> anyhow::Result<f32> {
+        let frame = self.transfer(CMD_READ_ANGLE)?;
+        if frame.count_ones() % 2 != 0 {
+            anyhow::bail!("parity error in frame {:#06x}", frame);
+        }
+        if frame & FRAME_ERROR != 0 {
+            self.transfer(CMD_CLEAR_ERROR)?;
+            self.transfer(CMD_READ_ANGLE)?;
+            anyhow::bail!("error flag set in frame {:#06x}", frame);
+        }
+        let counts = frame & 0x3FFF;
+        Ok(counts as f32 * 360.0 / COUNTS_PER_TURN)
+    }
+}
+
+impl SteeringSource for As5048<'_> {
+    fn angle(&mut self) -> Option<f32> {
+        if self.supervisor.degraded() {
+            if !self.supervisor.should_retry() {
+                return None;
+            }
+            let recovered = self.reset();
+            self.supervisor.retried(recovered.is_ok());
+            if let Err(e) = recovered {
+                warn!(
+                    "Failed to recover AS5048: {:?}, retrying in {:?}",
+                    e,
+                    self.supervisor.backoff()
+                );
+                return None;
+            }
+            info!("AS5048 recovered");
+        }
+        let angle = match self.raw_angle() {
+            Ok(angle) => {
+                self.supervisor.success();
+                angle
+            }
+            Err(e) => {
+                warn!("Failed to read AS5048: {:?}", e);
+                if self.supervisor.failure() {
+                    warn!("AS5048 lost");
+                }
+                return None;
+            }
+        };
+        let angle = if self.invert { -angle } else { angle };
+        let angle = self.turns.update(wrap_angle(angle));
+        if self.turns.delta().abs() > STILL_STEP {
+            self.still_since = Instant::now();
+        }
+        Some(angle)
+    }
+
+    fn set_zero(&mut self, zero: f32) {
+        self.turns.set_zero(zero);
+    }
+
+    fn recenter(&mut self) -> f32 {
+        self.turns.recenter()
+    }
+
+    fn still_for(&self) -> Duration {
+        self.still_since.elapsed()
+    }
+
+    fn degraded(&self) -> bool {
+        self.supervisor.degraded()
+    }
+}
@@ -0,0 +1,122 @@
+use super::{SteeringSource, Supervisor};
+use esp32_ble_steering_rs::fusion::{wrap_angle, TurnCounter};
+use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::i2c::I2cDriver;
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+const ADDRESS: u8 = 0x36;
+const REG_STATUS: u8 = 0x0B;
+const REG_RAW_ANGLE: u8 = 0x0C;
+const STATUS_MAGNET_DETECTED: u8 = 1 << 5;
+const STATUS_MAGNET_TOO_WEAK: u8 = 1 << 4;
+const STATUS_MAGNET_TOO_STRONG: u8 = 1 << 3;
+const COUNTS_PER_TURN: f32 = 4096.0;
+/// Angle change (degrees) per reading below which the wheel counts as held still.
+const STILL_STEP: f32 = 0.2;
+
+/// AS5600 12-bit magnetic angle sensor on I2C.
+pub struct As5600<'a> {
+    i2c: I2cDriver<'a>,
+    invert: bool,
+    turns: TurnCounter,
+    still_since: Instant,
+    supervisor: Supervisor,
+}
+
+impl<'a> As5600<'a> {
+    pub fn new(i2c: I2cDriver<'a>, invert: bool) -> anyhow::Result<Self> {
+        let mut res = Self {
+            i2c,
+            invert,
+            turns: TurnCounter::new(),
+            still_since: Instant::now(),
+            supervisor: Supervisor::new(),
+        };
+        res.check_magnet()?;
+        Ok(res)
+    }
+
+    /// Reads the status and reports how the magnet sits.
+    fn check_magnet(&mut self) -> anyhow::Result<()> {
+        let mut status = [0u8; 1];
+        self.i2c
+            .write_read(ADDRESS, &[REG_STATUS], &mut status, BLOCK)?;
+        let status = status[0];
+        if status & STATUS_MAGNET_DETECTED == 0 {
+            warn!("AS5600: no magnet detected");
+        } else if status & STATUS_MAGNET_TOO_WEAK != 0 {
+            warn!("AS5600: magnet too weak");
+        } else if status & STATUS_MAGNET_TOO_STRONG != 0 {
+            warn!("AS5600: magnet too strong");
+        } else {
+            info!("AS5600: magnet detected");
+        }
+        Ok(())
+    }
+
+    /// Reads the angle within a single turn, in degrees.
+    fn raw_angle(&mut self) -> anyhow::Result<f32> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(ADDRESS, &[REG_RAW_ANGLE], &mut buf, BLOCK)?;
+        let counts = u16::from_be_bytes(buf) & 0x0FFF;
+        Ok(counts as f32 * 360.0 / COUNTS_PER_TURN)
+    }
+}
+
+impl SteeringSource for As5600<'_> {
+    fn angle(&mut self) -> Option<f32> {
+        if self.supervisor.degraded() {
+            if !self.supervisor.should_retry() {
+                return None;
+            }
+            let recovered = self.check_magnet();
+            self.supervisor.retried(recovered.is_ok());
+            if let Err(e) = recovered {
+                warn!(
+                    "Failed to recover AS5600: {:?}, retrying in {:?}",
+                    e,
+                    self.supervisor.backoff()
+                );
+                return None;
+            }
+            info!("AS5600 recovered");
+        }
+        let angle = match self.raw_angle() {
+            Ok(angle) => {
+                self.supervisor.success();
+                angle
+            }
+            Err(e) => {
+                warn!("Failed to read AS5600: {:?}", e);
+                if self.supervisor.failure() {
+                    warn!("AS5600 lost");
+                }
+                return None;
+            }
+        };
+        let angle = if self.invert { -angle } else { angle };
+        let angle = self.turns.update(wrap_angle(angle));
+        if self.turns.delta().abs() > STILL_STEP {
+            self.still_since = Instant::now();
+        }
+        Some(angle)
+    }
+
+    fn set_zero(&mut self, zero: f32) {
+        self.turns.set_zero(zero);
+    }
+
+    fn recenter(&mut self) -> f32 {
+        self.turns.recenter()
+    }
+
+    fn still_for(&self) -> Duration {
+        self.still_since.elapsed()
+    }
+
+    fn degraded(&self) -> bool {
+        self.supervisor.degraded()
+    }
+}
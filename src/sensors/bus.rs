@@ -0,0 +1,82 @@
+use esp_idf_hal::delay::Ets;
+use esp_idf_hal::gpio::{AnyIOPin, PinDriver, Pull};
+use esp_idf_hal::i2c::{I2c, I2cConfig, I2cDriver};
+use esp_idf_hal::peripheral::Peripheral;
+
+// Half a clock period at 100 kHz
+const HALF_PERIOD_US: u32 = 5;
+
+/// An I2C bus whose driver can be torn down and set up again,
+/// so that a stuck bus can be recovered by bit-banging the pins.
+pub struct I2cBus<I2C: I2c + Peripheral<P = I2C>> {
+    i2c: I2C,
+    sda: AnyIOPin,
+    scl: AnyIOPin,
+    config: I2cConfig,
+}
+
+impl<I2C: I2c + Peripheral<P = I2C>> I2cBus<I2C> {
+    pub fn new(i2c: I2C, sda: AnyIOPin, scl: AnyIOPin, config: I2cConfig) -> Self {
+        Self {
+            i2c,
+            sda,
+            scl,
+            config,
+        }
+    }
+
+    /// Creates a driver for the bus.
+    ///
+    /// Any driver created before must have been dropped.
+    pub fn driver(&mut self) -> anyhow::Result<I2cDriver<'static>> {
+        // SAFETY: the bus hands out one driver at a time, and the pins are
+        // only bit-banged in `recover` while no driver exists
+        let driver = unsafe {
+            I2cDriver::new(
+                self.i2c.clone_unchecked(),
+                self.sda.clone_unchecked(),
+                self.scl.clone_unchecked(),
+                &self.config,
+            )?
+        };
+        Ok(driver)
+    }
+
+    /// Releases a device holding SDA low, e.g. after a reset in the middle
+    /// of a read, by clocking SCL up to nine times and sending a STOP.
+    ///
+    /// Any driver created before must have been dropped.
+    /// Returns whether SDA is free afterwards.
+    pub fn recover(&mut self) -> anyhow::Result<bool> {
+        // SAFETY: see `driver`
+        let mut sda = PinDriver::input_output_od(unsafe { self.sda.clone_unchecked() })?;
+        let mut scl = PinDriver::input_output_od(unsafe { self.scl.clone_unchecked() })?;
+        sda.set_pull(Pull::Up)?;
+        scl.set_pull(Pull::Up)?;
+        sda.set_high()?;
+        scl.set_high()?;
+        Ets::delay_us(HALF_PERIOD_US);
+
+        for _ in 0..9 {
+            if sda.is_high() {
+                break;
+            }
+            scl.set_low()?;
+            Ets::delay_us(HALF_PERIOD_US);
+            scl.set_high()?;
+            Ets::delay_us(HALF_PERIOD_US);
+        }
+
+        // STOP condition: SDA rises while SCL is high
+        scl.set_low()?;
+        Ets::delay_us(HALF_PERIOD_US);
+        sda.set_low()?;
+        Ets::delay_us(HALF_PERIOD_US);
+        scl.set_high()?;
+        Ets::delay_us(HALF_PERIOD_US);
+        sda.set_high()?;
+        Ets::delay_us(HALF_PERIOD_US);
+
+        Ok(sda.is_high())
+    }
+}
@@ -0,0 +1,369 @@
+use esp32_ble_steering_rs::health::factory_self_test;
+use esp_idf_hal::delay::{Ets, FreeRtos, TickType};
+use esp_idf_hal::i2c::I2cDriver;
+use std::f32::consts::PI;
+
+pub const ADDRESS: u8 = 0x68;
+/// Address of a second sensor on the same bus, with AD0 pulled high.
+pub const ALT_ADDRESS: u8 = 0x69;
+// AK8963 magnetometer of the MPU9250/9255, reached through the I2C bypass
+const MAG_ADDRESS: u8 = 0x0C;
+
+const TIMEOUT: TickType = TickType::new_millis(20);
+
+const REG_SELF_TEST_GYRO: u8 = 0x00; // X, Y, Z
+const REG_SELF_TEST_ACCEL: u8 = 0x0D; // X, Y, Z
+const REG_SMPLRT_DIV: u8 = 0x19;
+const REG_CONFIG: u8 = 0x1A;
+const REG_GYRO_CONFIG: u8 = 0x1B;
+const REG_ACCEL_CONFIG: u8 = 0x1C;
+const REG_ACCEL_CONFIG2: u8 = 0x1D;
+const REG_FIFO_EN: u8 = 0x23;
+const REG_INT_PIN_CFG: u8 = 0x37;
+const REG_INT_STATUS: u8 = 0x3A;
+const REG_ACCEL_XOUT_H: u8 = 0x3B;
+const REG_USER_CTRL: u8 = 0x6A;
+const REG_PWR_MGMT_1: u8 = 0x6B;
+const REG_FIFO_COUNTH: u8 = 0x72;
+const REG_FIFO_R_W: u8 = 0x74;
+const REG_WHO_AM_I: u8 = 0x75;
+
+const MAG_REG_WIA: u8 = 0x00;
+const MAG_REG_ST1: u8 = 0x02;
+const MAG_REG_HXL: u8 = 0x03; // X, Y, Z little endian, then ST2
+const MAG_REG_CNTL1: u8 = 0x0A;
+
+const CONFIG_FIFO_MODE: u8 = 1 << 6; // Keep old samples when the FIFO is full
+const FIFO_EN_TEMP: u8 = 0x80;
+const FIFO_EN_GYRO: u8 = 0x70;
+const FIFO_EN_ACCEL: u8 = 0x08;
+const INT_PIN_CFG_BYPASS_EN: u8 = 1 << 1;
+const INT_STATUS_FIFO_OFLOW: u8 = 1 << 4;
+const USER_CTRL_FIFO_EN: u8 = 1 << 6;
+const USER_CTRL_FIFO_RST: u8 = 1 << 2;
+const PWR_MGMT_1_H_RESET: u8 = 1 << 7;
+const PWR_MGMT_1_CLKSEL_PLL: u8 = 0x01;
+const SELF_TEST_XYZ: u8 = 0xE0;
+const MAG_WIA: u8 = 0x48;
+const MAG_ST1_DRDY: u8 = 1 << 0;
+const MAG_ST2_HOFL: u8 = 1 << 3;
+const MAG_CNTL1_16BIT_100HZ: u8 = 0x16;
+
+// MPU6500, MPU9250, MPU9255
+const WHO_AM_I: [u8; 3] = [0x70, 0x71, 0x73];
+
+// ±4 g and ±1000 dps, so that quick counter-steering does not saturate the gyro
+const ACCEL_FS_4G: u8 = 1 << 3;
+const GYRO_FS_1000DPS: u8 = 2 << 3;
+const ACCEL_LSB_PER_G: f32 = 8192.0;
+const GYRO_LSB_PER_DPS: f32 = 32.8;
+const TEMP_LSB_PER_C: f32 = 333.87;
+const TEMP_OFFSET_C: f32 = 21.0;
+
+const INTERNAL_RATE: u16 = 1000;
+const PACKET_SIZE: usize = 14; // accel xyz, temperature, gyro xyz
+const FIFO_SIZE: usize = 512;
+const MAX_PACKETS: usize = FIFO_SIZE / PACKET_SIZE;
+const SELF_TEST_SAMPLES: usize = 100;
+
+/// Low-pass filter bandwidth of the gyro and accelerometer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dlpf {
+    Hz184 = 1,
+    Hz92 = 2,
+    Hz41 = 3,
+    Hz20 = 4,
+    Hz10 = 5,
+    Hz5 = 6,
+}
+
+impl Dlpf {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Dlpf::Hz184),
+            2 => Some(Dlpf::Hz92),
+            3 => Some(Dlpf::Hz41),
+            4 => Some(Dlpf::Hz20),
+            5 => Some(Dlpf::Hz10),
+            6 => Some(Dlpf::Hz5),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImuConfig {
+    /// Output data rate in Hz, from 4 to 1000.
+    pub sample_rate: u16,
+    pub dlpf: Dlpf,
+}
+
+impl Default for ImuConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 200,
+            dlpf: Dlpf::Hz41,
+        }
+    }
+}
+
+impl ImuConfig {
+    fn divider(&self) -> u8 {
+        (INTERNAL_RATE / self.sample_rate.clamp(4, INTERNAL_RATE) - 1) as u8
+    }
+
+    /// The time between two samples, in seconds.
+    pub fn sample_period(&self) -> f32 {
+        (1 + self.divider() as u16) as f32 / INTERNAL_RATE as f32
+    }
+}
+
+/// One accelerometer, gyro and temperature sample.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sample {
+    /// Acceleration in g.
+    pub accel: [f32; 3],
+    /// Rotation rate in rad/s.
+    pub gyro: [f32; 3],
+    /// Die temperature in °C.
+    pub temp: f32,
+}
+
+/// Self-test responses relative to the factory ones, 1.0 being a perfect match.
+#[derive(Debug, Clone, Copy)]
+pub struct SelfTest {
+    pub gyro: [f32; 3],
+    pub accel: [f32; 3],
+}
+
+/// Register-level driver for the MPU6500 family (including the MPU9250/9255),
+/// buffering samples in the on-chip FIFO at a fixed rate.
+///
+/// The bus driver is passed to each call, so that two sensors can share it.
+pub struct Mpu6500 {
+    address: u8,
+    buf: [u8; MAX_PACKETS * PACKET_SIZE],
+    mag: bool,
+}
+
+impl Mpu6500 {
+    /// Resets and configures the sensor at `address`. With `mag`, also enables
+    /// its magnetometer if it has one; only one sensor on a bus may do so, as
+    /// they all answer at the same address.
+    pub fn new(
+        i2c: &mut I2cDriver,
+        address: u8,
+        config: &ImuConfig,
+        mag: bool,
+    ) -> anyhow::Result<Self> {
+        let mut res = Self {
+            address,
+            buf: [0; MAX_PACKETS * PACKET_SIZE],
+            mag: false,
+        };
+
+        let who_am_i = res.read(i2c, REG_WHO_AM_I)?;
+        if !WHO_AM_I.contains(&who_am_i) {
+            anyhow::bail!("unexpected WHO_AM_I {:#04x}", who_am_i);
+        }
+
+        res.write(i2c, REG_PWR_MGMT_1, PWR_MGMT_1_H_RESET)?;
+        FreeRtos::delay_ms(100);
+        res.write(i2c, REG_PWR_MGMT_1, PWR_MGMT_1_CLKSEL_PLL)?;
+        FreeRtos::delay_ms(10);
+
+        res.configure(i2c, config)?;
+        // The MPU6500 has no magnetometer, so not finding one is fine
+        res.mag = mag && res.enable_mag(i2c).unwrap_or(false);
+        Ok(res)
+    }
+
+    /// Whether the magnetometer was found.
+    pub fn has_mag(&self) -> bool {
+        self.mag
+    }
+
+    fn enable_mag(&mut self, i2c: &mut I2cDriver) -> anyhow::Result<bool> {
+        self.write(i2c, REG_INT_PIN_CFG, INT_PIN_CFG_BYPASS_EN)?;
+        let mut wia = [0u8; 1];
+        i2c.write_read(MAG_ADDRESS, &[MAG_REG_WIA], &mut wia, TIMEOUT.ticks())?;
+        if wia[0] != MAG_WIA {
+            return Ok(false);
+        }
+        i2c.write(
+            MAG_ADDRESS,
+            &[MAG_REG_CNTL1, MAG_CNTL1_16BIT_100HZ],
+            TIMEOUT.ticks(),
+        )?;
+        Ok(true)
+    }
+
+    /// Reads the magnetometer, if it has a new measurement, in LSB and
+    /// turned to the accelerometer and gyro axes.
+    pub fn read_mag(&mut self, i2c: &mut I2cDriver) -> anyhow::Result<Option<[f32; 3]>> {
+        if !self.mag {
+            return Ok(None);
+        }
+        let mut st1 = [0u8; 1];
+        i2c.write_read(MAG_ADDRESS, &[MAG_REG_ST1], &mut st1, TIMEOUT.ticks())?;
+        if st1[0] & MAG_ST1_DRDY == 0 {
+            return Ok(None);
+        }
+        // Reading ST2 as well releases the data registers for the next measurement
+        let mut buf = [0u8; 7];
+        i2c.write_read(MAG_ADDRESS, &[MAG_REG_HXL], &mut buf, TIMEOUT.ticks())?;
+        if buf[6] & MAG_ST2_HOFL != 0 {
+            return Ok(None);
+        }
+        let value = |i: usize| i16::from_le_bytes([buf[2 * i], buf[2 * i + 1]]) as f32;
+        // The AK8963 has X and Y swapped and Z reversed
+        Ok(Some([value(1), value(0), -value(2)]))
+    }
+
+    fn configure(&mut self, i2c: &mut I2cDriver, config: &ImuConfig) -> anyhow::Result<()> {
+        self.write(i2c, REG_CONFIG, CONFIG_FIFO_MODE | config.dlpf as u8)?;
+        self.write(i2c, REG_SMPLRT_DIV, config.divider())?;
+        self.write(i2c, REG_GYRO_CONFIG, GYRO_FS_1000DPS)?;
+        self.write(i2c, REG_ACCEL_CONFIG, ACCEL_FS_4G)?;
+        self.write(i2c, REG_ACCEL_CONFIG2, config.dlpf as u8)?;
+
+        self.write(
+            i2c,
+            REG_FIFO_EN,
+            FIFO_EN_TEMP | FIFO_EN_GYRO | FIFO_EN_ACCEL,
+        )?;
+        self.reset_fifo(i2c)
+    }
+
+    /// Runs the built-in self-test, then restores `config`.
+    ///
+    /// Follows the MPU6500 self-test procedure: the outputs are averaged with
+    /// and without the self-test actuation, and the difference compared to
+    /// the factory trim. The sensor has to be still meanwhile.
+    pub fn self_test(
+        &mut self,
+        i2c: &mut I2cDriver,
+        config: &ImuConfig,
+    ) -> anyhow::Result<SelfTest> {
+        let result = self.measure_self_test(i2c);
+        // Also when the test broke off halfway, which leaves the sensor at
+        // ±250 dps and ±2 g with the FIFO off
+        let configured = self.configure(i2c, config);
+        let result = result?;
+        configured?;
+        Ok(result)
+    }
+
+    fn measure_self_test(&mut self, i2c: &mut I2cDriver) -> anyhow::Result<SelfTest> {
+        self.write(i2c, REG_FIFO_EN, 0)?;
+        self.write(i2c, REG_SMPLRT_DIV, 0)?;
+        self.write(i2c, REG_CONFIG, Dlpf::Hz92 as u8)?;
+        self.write(i2c, REG_ACCEL_CONFIG2, Dlpf::Hz92 as u8)?;
+        self.write(i2c, REG_GYRO_CONFIG, 0)?; // ±250 dps
+        self.write(i2c, REG_ACCEL_CONFIG, 0)?; // ±2 g
+        FreeRtos::delay_ms(20);
+        let normal = self.average(i2c)?;
+
+        self.write(i2c, REG_GYRO_CONFIG, SELF_TEST_XYZ)?;
+        self.write(i2c, REG_ACCEL_CONFIG, SELF_TEST_XYZ)?;
+        FreeRtos::delay_ms(20);
+        let actuated = self.average(i2c)?;
+
+        self.write(i2c, REG_GYRO_CONFIG, 0)?;
+        self.write(i2c, REG_ACCEL_CONFIG, 0)?;
+        FreeRtos::delay_ms(20);
+
+        let mut codes = [0u8; 6];
+        for (i, code) in codes.iter_mut().enumerate() {
+            *code = if i < 3 {
+                self.read(i2c, REG_SELF_TEST_GYRO + i as u8)?
+            } else {
+                self.read(i2c, REG_SELF_TEST_ACCEL + i as u8 - 3)?
+            };
+        }
+        let ratio = |i: usize| (actuated[i] - normal[i]).abs() / factory_self_test(codes[i]);
+        Ok(SelfTest {
+            gyro: [0, 1, 2].map(ratio),
+            accel: [3, 4, 5].map(ratio),
+        })
+    }
+
+    /// Averages the gyro and accelerometer outputs, in LSB, gyro first.
+    fn average(&mut self, i2c: &mut I2cDriver) -> anyhow::Result<[f32; 6]> {
+        let mut sum = [0.0; 6];
+        let mut buf = [0u8; 14];
+        for _ in 0..SELF_TEST_SAMPLES {
+            i2c.write_read(self.address, &[REG_ACCEL_XOUT_H], &mut buf, TIMEOUT.ticks())?;
+            let value = |i: usize| i16::from_be_bytes([buf[2 * i], buf[2 * i + 1]]) as f32;
+            // Registers are accel xyz, temperature, gyro xyz
+            for (i, reg) in [4, 5, 6, 0, 1, 2].into_iter().enumerate() {
+                sum[i] += value(reg);
+            }
+            Ets::delay_us(1000);
+        }
+        Ok(sum.map(|x| x / SELF_TEST_SAMPLES as f32))
+    }
+
+    fn read(&mut self, i2c: &mut I2cDriver, reg: u8) -> anyhow::Result<u8> {
+        let mut buf = [0u8; 1];
+        i2c.write_read(self.address, &[reg], &mut buf, TIMEOUT.ticks())?;
+        Ok(buf[0])
+    }
+
+    fn write(&mut self, i2c: &mut I2cDriver, reg: u8, value: u8) -> anyhow::Result<()> {
+        i2c.write(self.address, &[reg, value], TIMEOUT.ticks())?;
+        Ok(())
+    }
+
+    fn reset_fifo(&mut self, i2c: &mut I2cDriver) -> anyhow::Result<()> {
+        self.write(i2c, REG_USER_CTRL, USER_CTRL_FIFO_RST)?;
+        self.write(i2c, REG_USER_CTRL, USER_CTRL_FIFO_EN)
+    }
+
+    /// Appends all samples waiting in the FIFO to `samples`, oldest first.
+    ///
+    /// If the FIFO overflowed, it is cleared and an error is returned,
+    /// as the samples kept no longer follow on from the previous read.
+    pub fn read_fifo(
+        &mut self,
+        i2c: &mut I2cDriver,
+        samples: &mut Vec<Sample>,
+    ) -> anyhow::Result<()> {
+        if self.read(i2c, REG_INT_STATUS)? & INT_STATUS_FIFO_OFLOW != 0 {
+            self.reset_fifo(i2c)?;
+            anyhow::bail!("FIFO overflow");
+        }
+
+        let mut count = [0u8; 2];
+        i2c.write_read(
+            self.address,
+            &[REG_FIFO_COUNTH],
+            &mut count,
+            TIMEOUT.ticks(),
+        )?;
+        let count = (u16::from_be_bytes(count) & 0x1FFF) as usize;
+        let packets = (count / PACKET_SIZE).min(MAX_PACKETS);
+        if packets == 0 {
+            return Ok(());
+        }
+
+        let len = packets * PACKET_SIZE;
+        i2c.write_read(
+            self.address,
+            &[REG_FIFO_R_W],
+            &mut self.buf[..len],
+            TIMEOUT.ticks(),
+        )?;
+
+        let gyro_scale = PI / 180.0 / GYRO_LSB_PER_DPS;
+        for packet in self.buf[..len].chunks_exact(PACKET_SIZE) {
+            let value = |i: usize| i16::from_be_bytes([packet[2 * i], packet[2 * i + 1]]) as f32;
+            samples.push(Sample {
+                accel: [0, 1, 2].map(|i| value(i) / ACCEL_LSB_PER_G),
+                gyro: [4, 5, 6].map(|i| value(i) * gyro_scale),
+                temp: value(3) / TEMP_LSB_PER_C + TEMP_OFFSET_C,
+            });
+        }
+        Ok(())
+    }
+}
@@ -1,2 +1,20 @@
 mod mpu;
-pub use mpu::*;
\ No newline at end of file
+pub use mpu::*;
+
+mod imu;
+pub use imu::*;
+
+mod source;
+pub use source::*;
+
+mod as5600;
+pub use as5600::*;
+
+mod as5048;
+pub use as5048::*;
+
+mod bus;
+pub use bus::*;
+
+mod supervisor;
+pub use supervisor::*;
@@ -1,199 +1,630 @@
-use esp_idf_hal::delay::Delay;
-use esp_idf_hal::i2c::I2cDriver;
-use log::warn;
-use mpu9250::{I2cDevice, Imu, Mpu9250};
+use super::{I2cBus, ImuConfig, Mpu6500, Sample, SteeringSource, Supervisor, ADDRESS, ALT_ADDRESS};
+use esp32_ble_steering_rs::fusion::{
+    gravity_to_roll, quaternion_to_pitch, quaternion_to_roll, wrap_angle, AdaptiveGain, AxisMode,
+    GyroTempLearner, GyroTempModel, Madgwick, MagCalibration, Mount, TurnCounter,
+};
+use esp32_ble_steering_rs::gesture::{Gesture, GestureConfig, GestureRecognizer};
+use esp32_ble_steering_rs::health::{Health, HealthFlags, RestCheck};
+use esp_idf_hal::i2c::{I2c, I2cDriver};
+use esp_idf_hal::peripheral::Peripheral;
+use log::{info, warn};
 use std::f32::consts::PI;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-fn quaternion_to_roll(q: [f32; 4], roll: f32) -> f32 {
-    // atan2(2.0f * (q[0] * q[1] + q[2] * q[3]),
-    // q[0] * q[0] - q[1] * q[1] - q[2] * q[2] + q[3] * q[3])
-    let mut new_roll = (-2.0 * (q[0] * q[1] + q[2] * q[3]))
-        .atan2(q[0] * q[0] - q[1] * q[1] - q[2] * q[2] + q[3] * q[3]);
-    new_roll = new_roll * 180.0 / PI - 90.0;
-    if new_roll < -180.0 {
-        new_roll += 360.0;
-    }
+/// Gyro rate (rad/s) below which the wheel counts as held still.
+const STILL_RATE: f32 = 0.05;
+/// How long the fusion runs at the boot gain after (re)initialization.
+const CONVERGE_TIME: Duration = Duration::from_secs(3);
+/// Weight of each new sample in the running gravity average.
+const GRAVITY_WEIGHT: f32 = 0.02;
+/// How often a changed gyro bias model is handed out to be persisted.
+const GYRO_TEMP_SAVE_INTERVAL: Duration = Duration::from_secs(600);
+/// Weight of each magnetometer heading in the yaw, against gyro drift.
+const MAG_WEIGHT: f32 = 0.01;
+/// Seconds of samples at rest the checks run over after the self-test.
+const REST_CHECK_TIME: f32 = 1.0;
+/// Angle between two sensors, in degrees, past which they disagree.
+const DISAGREE_ANGLE: f32 = 10.0;
+/// How long two sensors have to disagree before only one is used.
+const DISAGREE_TIME: Duration = Duration::from_millis(500);
+/// Weight of each new sample in the running gyro noise estimate.
+const NOISE_WEIGHT: f32 = 0.01;
+/// Noise floor as standard deviation in rad/s, so that neither sensor
+/// takes all the weight.
+const MIN_NOISE: f32 = 0.001;
+/// Noise variance, in (rad/s)², charged for a sample repeated exactly.
+const STUCK_NOISE: f32 = 1.0;
+
+/// One IMU on the bus, with its own fusion filter, so that two of them can
+/// be compared and either one can carry on alone.
+struct Unit {
+    address: u8,
+    /// Whether the sensor was found, and so is expected to be there.
+    present: bool,
+    mpu: Option<Mpu6500>,
+    supervisor: Supervisor,
+    /// Samples of the last read, turned to the wheel axes and compensated.
+    samples: Vec<Sample>,
+    gravity: [f32; 3],
+    yaw: f32,
+    mag_calibration: MagCalibration,
+    madgwick: Madgwick,
+    gyro_temp: GyroTempLearner,
+    gyro_temp_changed: bool,
+    gyro_temp_saved: Instant,
+    gain: AdaptiveGain,
+    last: Sample,
+    /// Running variance of the gyro, from the change between samples.
+    noise: f32,
+    health: Health,
+    rest_check: Option<RestCheck>,
+}
 
-    let mut delta = new_roll - roll;
-    if delta > 180.0 {
-        delta -= 360.0;
-    } else if delta < -180.0 {
-        delta += 360.0;
+impl Unit {
+    fn new(address: u8) -> Self {
+        let madgwick = Madgwick::new();
+        Self {
+            address,
+            present: false,
+            mpu: None,
+            supervisor: Supervisor::new(),
+            samples: Vec::new(),
+            gravity: [0.0, 0.0, 1.0],
+            yaw: 0.0,
+            mag_calibration: MagCalibration::new(),
+            gain: AdaptiveGain::new(madgwick.beta(), CONVERGE_TIME),
+            madgwick,
+            gyro_temp: GyroTempLearner::new(GyroTempModel::default()),
+            gyro_temp_changed: false,
+            gyro_temp_saved: Instant::now(),
+            last: Sample::default(),
+            noise: MIN_NOISE * MIN_NOISE,
+            health: Health {
+                flags: HealthFlags::UNTESTED,
+                ..Health::default()
+            },
+            rest_check: None,
+        }
     }
 
-    roll + delta
-}
+    fn has_mag(&self) -> bool {
+        self.mpu.as_ref().is_some_and(Mpu6500::has_mag)
+    }
 
-pub struct MpuSensor<'a> {
-    mpu: Option<Mpu9250<I2cDevice<I2cDriver<'a>>, Imu>>,
-    roll: f32,
-    q: [f32; 4],
-    gbias: [f32; 3],
-    beta: f32,
-    zeta: f32,
-    updated: Instant,
-}
+    /// Whether the sensor is up and passed its checks.
+    fn usable(&self) -> bool {
+        self.mpu.is_some() && self.rest_check.is_none() && !self.health.failed()
+    }
 
-impl<'a> MpuSensor<'a> {
-    pub fn new(i2c: I2cDriver<'a>) -> anyhow::Result<Self> {
-        let mut delay = Delay::new_default();
-        let mpu = match Mpu9250::imu_default(i2c, &mut delay) {
-            Ok(mpu) => Some(mpu),
+    /// Sets up the sensor, enabling its magnetometer if `mag`, and records
+    /// the outcome with the supervisor. Returns whether it came up.
+    fn init(&mut self, i2c: &mut I2cDriver, config: &ImuConfig, mag: bool) -> bool {
+        match Mpu6500::new(i2c, self.address, config, mag) {
+            Ok(mpu) => {
+                if mpu.has_mag() {
+                    info!("Magnetometer found, yaw will not drift");
+                }
+                self.mpu = Some(mpu);
+                self.supervisor.retried(true);
+                self.gain.restart();
+                self.start_checks(i2c, config);
+                true
+            }
             Err(e) => {
-                warn!("Failed to initialize MPU sensor: {:?}", e);
-                None
+                if self.supervisor.degraded() {
+                    self.supervisor.retried(false);
+                } else {
+                    self.supervisor.degrade();
+                }
+                if self.present {
+                    warn!(
+                        "Failed to initialize IMU at {:#04x}: {:?}, retrying in {:?}",
+                        self.address,
+                        e,
+                        self.supervisor.backoff()
+                    );
+                }
+                false
+            }
+        }
+    }
+
+    /// Runs the self-test once the sensor first comes up, and starts the
+    /// checks at rest, again after a recovery if they had not passed. Until
+    /// they pass, the sensor is not used.
+    fn start_checks(&mut self, i2c: &mut I2cDriver, config: &ImuConfig) {
+        if self.mpu.is_none() {
+            return;
+        }
+        if self.health.untested() {
+            self.self_test(i2c, config);
+        } else if self.rest_check.is_none() && !self.health.rest_failed() {
+            return;
+        }
+        self.health.flags.insert(HealthFlags::CHECKING);
+        self.rest_check = Some(RestCheck::new());
+    }
+
+    /// Runs the self-test. If it could not run, the sensor stays untested
+    /// and the test is tried again after the next checks at rest.
+    fn self_test(&mut self, i2c: &mut I2cDriver, config: &ImuConfig) {
+        let Some(mpu) = self.mpu.as_mut() else {
+            return;
+        };
+        match mpu.self_test(i2c, config) {
+            Ok(result) => {
+                info!("IMU at {:#04x} self-test: {:?}", self.address, result);
+                self.health.self_test(result.gyro, result.accel);
+            }
+            Err(e) => warn!(
+                "IMU at {:#04x} self-test could not run, trying again at rest: {:?}",
+                self.address, e
+            ),
+        }
+    }
+
+    /// Runs the fusion filter over all samples buffered by the sensor since
+    /// the last call, first trying to bring it back if it was lost.
+    fn update(&mut self, i2c: &mut I2cDriver, config: &ImuConfig, mount: Mount, mag: bool) {
+        self.samples.clear();
+        if self.mpu.is_none() {
+            if !self.supervisor.should_retry() || !self.init(i2c, config, mag) {
+                return;
             }
+            info!("IMU at {:#04x} recovered", self.address);
+        }
+        let Some(mpu) = self.mpu.as_mut() else {
+            return;
         };
+        let mut samples = std::mem::take(&mut self.samples);
+        match mpu.read_fifo(i2c, &mut samples) {
+            Ok(_) => self.supervisor.success(),
+            Err(e) => {
+                if self.supervisor.failure() {
+                    warn!("IMU at {:#04x} lost: {:?}", self.address, e);
+                    self.mpu = None;
+                }
+                samples.clear();
+                self.samples = samples;
+                return;
+            }
+        }
+
+        // The FIFO is filled at a fixed rate, so each sample is one period apart
+        let delta_t = config.sample_period();
+        for sample in samples.iter_mut() {
+            // Real sensors always jitter by a few LSB, so an exact repeat
+            // means the output is stuck
+            let change = if sample.accel == self.last.accel && sample.gyro == self.last.gyro {
+                STUCK_NOISE
+            } else {
+                sample
+                    .gyro
+                    .iter()
+                    .zip(self.last.gyro)
+                    .map(|(x, last)| (x - last) * (x - last))
+                    .sum::<f32>()
+                    / 2.0
+            };
+            self.noise += (change - self.noise) * NOISE_WEIGHT;
+            self.last = *sample;
+
+            for (gravity, accel) in self.gravity.iter_mut().zip(sample.accel) {
+                *gravity += (accel - *gravity) * GRAVITY_WEIGHT;
+            }
+
+            if let Some(check) = self.rest_check.as_mut() {
+                check.add(sample.accel, sample.gyro, delta_t);
+            }
+
+            // The filter runs on the compensated wheel-frame rate, so its
+            // bias is what the model still misses
+            let drift = mount.unrotate(self.madgwick.bias());
+            if self
+                .gyro_temp
+                .update(sample.accel, sample.gyro, sample.temp, drift, delta_t)
+            {
+                // The model now holds what the filter had estimated
+                self.madgwick.reset_bias();
+                self.gyro_temp_changed = true;
+            }
+            let gyro = self.gyro_temp.model().compensate(sample.gyro, sample.temp);
+
+            let accel = mount.rotate(sample.accel);
+            let gyro = mount.rotate(gyro);
+            self.madgwick.set_beta(self.gain.beta(accel, gyro, delta_t));
+            self.madgwick.update(accel, gyro, delta_t);
 
-        let gyro_meas_error = PI * (40.0 / 180.0);
-        let beta = (3.0 / 4.0_f32).sqrt() * gyro_meas_error;
-        let gyro_meas_drift = PI * (2.0 / 180.0);
-        let zeta = (3.0 / 4.0_f32).sqrt() * gyro_meas_drift;
-        let updated = Instant::now();
-
-        Ok(Self {
-            mpu,
-            roll: 0.0,
-            q: [1.0, 0.0, 0.0, 0.0],
-            gbias: [0.0, 0.0, 0.0],
-            beta,
-            zeta,
-            updated,
+            // Same sign convention as the roll
+            self.yaw = wrap_angle(self.yaw - gyro[2] * delta_t * 180.0 / PI);
+
+            sample.accel = accel;
+            sample.gyro = gyro;
+        }
+        self.samples = samples;
+
+        match self.mpu.as_mut().map(|mpu| mpu.read_mag(i2c)) {
+            Some(Ok(Some(mag))) => {
+                // Flat, so the heading comes straight from the horizontal axes
+                if let Some(mag) = self.mag_calibration.update(mount.rotate(mag)) {
+                    let heading = mag[1].atan2(mag[0]) * 180.0 / PI;
+                    self.yaw = wrap_angle(self.yaw + wrap_angle(heading - self.yaw) * MAG_WEIGHT);
+                }
+            }
+            Some(Err(e)) => warn!("Failed to read magnetometer: {:?}", e),
+            _ => {}
+        }
+
+        if let Some(check) = &self.rest_check {
+            if check.time() >= REST_CHECK_TIME {
+                let rest_failed = self.health.rest_failed();
+                self.health.rest(check);
+                if self.health.rest_failed() {
+                    if !rest_failed {
+                        warn!(
+                            "IMU at {:#04x} failed its checks at rest, checking again: {:?}",
+                            self.address, self.health
+                        );
+                    }
+                    self.health.flags.insert(HealthFlags::CHECKING);
+                    self.rest_check = Some(RestCheck::new());
+                    return;
+                }
+                if self.health.untested() {
+                    // The wheel was just left alone, a good time to try again
+                    self.self_test(i2c, config);
+                    if self.health.untested() {
+                        self.health.flags.insert(HealthFlags::CHECKING);
+                        self.rest_check = Some(RestCheck::new());
+                        return;
+                    }
+                }
+                self.rest_check = None;
+                if self.health.failed() {
+                    warn!(
+                        "IMU at {:#04x} failed its checks, not steering with it: {:?}",
+                        self.address, self.health
+                    );
+                } else {
+                    info!(
+                        "IMU at {:#04x} checks passed: {:?}",
+                        self.address, self.health
+                    );
+                    // Start from the noise measured at rest
+                    self.noise = self.health.gyro_noise * self.health.gyro_noise;
+                }
+            }
+        }
+    }
+
+    /// The angle for the axis mode, once the sensor is usable.
+    fn angle(&self, mode: AxisMode, mount: Mount) -> Option<f32> {
+        if !self.usable() {
+            return None;
+        }
+        Some(match mode {
+            AxisMode::Roll => quaternion_to_roll(self.madgwick.quaternion()),
+            AxisMode::Pitch => quaternion_to_pitch(self.madgwick.quaternion()),
+            AxisMode::Yaw => self.yaw,
+            AxisMode::Tilt => gravity_to_roll(mount.rotate(self.gravity)),
         })
     }
 
-    pub fn roll(&mut self) -> Option<f32> {
-        let mpu = match self.mpu {
-            Some(ref mut mpu) => mpu,
-            None => {
-                warn!("MPU sensor not initialized");
-                return None;
-            }
+    /// The gyro noise estimate, as variance in (rad/s)².
+    fn noise(&self) -> f32 {
+        self.noise.max(MIN_NOISE * MIN_NOISE)
+    }
+}
+
+/// Steering angle fused from one IMU, or two on the same bus for redundancy.
+///
+/// With two, their angles are averaged weighted by each one's noise. When
+/// they disagree for a while, only the quieter one is used, and when one is
+/// lost or fails its checks, the other carries on alone. The yaw comes from
+/// the one with the magnetometer, if only one has it.
+pub struct MpuSensor<I2C: I2c + Peripheral<P = I2C>> {
+    bus: I2cBus<I2C>,
+    i2c: Option<I2cDriver<'static>>,
+    config: ImuConfig,
+    /// Supervises the bus, which is recovered when all sensors are lost.
+    supervisor: Supervisor,
+    units: [Unit; 2],
+    /// The unit gestures, stillness, pitch and gravity come from.
+    primary: usize,
+    /// Bits of the units that were usable on the last read.
+    usable: u8,
+    /// Bits of the units the angle came from on the last read.
+    in_use: u8,
+    disagree_since: Option<Instant>,
+    disagree: bool,
+    turns: TurnCounter,
+    mount: Mount,
+    mode: AxisMode,
+    still_since: Instant,
+    gestures: GestureRecognizer,
+    gesture: Option<Gesture>,
+}
+
+impl<I2C: I2c + Peripheral<P = I2C>> MpuSensor<I2C> {
+    pub fn new(bus: I2cBus<I2C>, config: ImuConfig) -> anyhow::Result<Self> {
+        let mut res = Self {
+            bus,
+            i2c: None,
+            config,
+            supervisor: Supervisor::new(),
+            units: [Unit::new(ADDRESS), Unit::new(ALT_ADDRESS)],
+            primary: 0,
+            usable: 0,
+            in_use: 0,
+            disagree_since: None,
+            disagree: false,
+            turns: TurnCounter::new(),
+            mount: Mount::IDENTITY,
+            mode: AxisMode::Roll,
+            still_since: Instant::now(),
+            gestures: GestureRecognizer::new(GestureConfig::default()),
+            gesture: None,
         };
-        let all = match mpu.all::<[f32; 3]>() {
-            Ok(all) => all,
+        if let Err(e) = res.init() {
+            warn!("Failed to initialize MPU sensor: {:?}", e);
+            res.supervisor.degrade();
+        }
+        Ok(res)
+    }
+
+    /// Sets up the bus and the sensors on it. Until a sensor has been found,
+    /// both addresses are tried, afterwards only the ones that were found.
+    fn init(&mut self) -> anyhow::Result<()> {
+        if !self.bus.recover()? {
+            warn!("I2C bus still held low after recovery");
+        }
+        let mut i2c = self.bus.driver()?;
+        let probing = !self.units.iter().any(|unit| unit.present);
+        // Both sensors may have a magnetometer, but only one can be on the bus
+        let mut mag = true;
+        for unit in self.units.iter_mut().filter(|unit| probing || unit.present) {
+            if unit.init(&mut i2c, &self.config, mag) {
+                if probing {
+                    info!("IMU found at {:#04x}", unit.address);
+                }
+                unit.present = true;
+                mag &= !unit.has_mag();
+            }
+        }
+        if self.units.iter().all(|unit| unit.mpu.is_none()) {
+            anyhow::bail!("no IMU answered");
+        }
+        self.i2c = Some(i2c);
+        Ok(())
+    }
+
+    /// Tries to bring the bus back after all sensors were lost, if a retry is due.
+    fn reinit(&mut self) {
+        if !self.supervisor.should_retry() {
+            return;
+        }
+        match self.init() {
+            Ok(_) => {
+                info!("MPU sensor recovered");
+                self.supervisor.retried(true);
+            }
             Err(e) => {
-                warn!("Failed to read DMP data: {:?}", e);
-                return None;
+                self.supervisor.retried(false);
+                warn!(
+                    "Failed to re-initialize MPU sensor: {:?}, retrying in {:?}",
+                    e,
+                    self.supervisor.backoff()
+                );
+            }
+        }
+    }
+
+    /// Reads all sensors, and returns the angle for the axis mode fused
+    /// from the ones that are usable.
+    fn read_angle(&mut self) -> Option<f32> {
+        if self.i2c.is_none() {
+            self.reinit();
+        }
+        let i2c = self.i2c.as_mut()?;
+        let mut mag = !self.units.iter().any(Unit::has_mag);
+        for unit in self.units.iter_mut().filter(|unit| unit.present) {
+            unit.update(i2c, &self.config, self.mount, mag);
+            mag &= !unit.has_mag();
+        }
+        if self.units.iter().all(|unit| unit.mpu.is_none()) {
+            warn!("All IMUs lost, recovering the bus");
+            // Drop the driver so the bus can be recovered
+            self.i2c = None;
+            self.supervisor.degrade();
+            return None;
+        }
+
+        let angle = self.fuse();
+
+        let primary = &self.units[self.primary];
+        let delta_t = self.config.sample_period();
+        for sample in &primary.samples {
+            let rate = sample.gyro.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if rate > STILL_RATE {
+                self.still_since = Instant::now();
+            }
+
+            if let Some(gesture) = self.gestures.update(sample.accel, sample.gyro, delta_t) {
+                self.gesture = Some(gesture);
+            }
+        }
+
+        angle.map(|angle| self.turns.update(angle))
+    }
+
+    /// Fuses the angles of the usable units, watching for disagreement,
+    /// and picks the primary unit.
+    fn fuse(&mut self) -> Option<f32> {
+        let angles = [0, 1].map(|i| self.units[i].angle(self.mode, self.mount));
+        let usable = angles
+            .iter()
+            .enumerate()
+            .filter(|(_, angle)| angle.is_some())
+            .fold(0u8, |bits, (i, _)| bits | 1 << i);
+        // A unit coming back integrates yaw from zero, so line it up first
+        if self.mode == AxisMode::Yaw && self.usable.count_ones() == 1 && usable == 0b11 {
+            let from = self.usable.trailing_zeros() as usize;
+            self.units[1 - from].yaw = self.units[from].yaw;
+        }
+        self.usable = usable;
+
+        // Only one unit gets the magnetometer, and the yaw of the other drifts
+        // freely, so in yaw mode the one with it steers and the other is kept
+        // lined up with it, ready to carry on alone
+        if let ([Some(_), Some(_)], AxisMode::Yaw) = (angles, self.mode) {
+            if let Some(from) = self.units.iter().position(Unit::has_mag) {
+                self.units[1 - from].yaw = self.units[from].yaw;
+                self.disagree_since = None;
+                self.disagree = false;
+                self.in_use = 1 << from;
+                self.primary = from;
+                return angles[from];
+            }
+        }
+
+        let [a, b] = match angles {
+            [Some(a), Some(b)] => [a, b],
+            [a, b] => {
+                self.disagree_since = None;
+                self.disagree = false;
+                self.in_use = usable;
+                self.primary = match (a, b) {
+                    (None, Some(_)) => 1,
+                    (Some(_), None) => 0,
+                    // Keep gestures and stillness going while the checks run
+                    _ => self
+                        .units
+                        .iter()
+                        .position(|unit| unit.mpu.is_some())
+                        .unwrap_or(0),
+                };
+                return a.or(b);
             }
         };
-        self.madgwick_quaternion_update(
-            all.accel[0],
-            all.accel[1],
-            all.accel[2],
-            all.gyro[0],
-            all.gyro[1],
-            all.gyro[2],
+
+        let diff = wrap_angle(b - a);
+        if diff.abs() > DISAGREE_ANGLE {
+            let since = *self.disagree_since.get_or_insert_with(Instant::now);
+            if !self.disagree && since.elapsed() >= DISAGREE_TIME {
+                warn!("IMUs disagree by {:.1}°, using the quieter one", diff);
+                self.disagree = true;
+            }
+        } else {
+            if self.disagree {
+                info!("IMUs agree again");
+            }
+            self.disagree_since = None;
+            self.disagree = false;
+        }
+
+        let (noise_a, noise_b) = (self.units[0].noise(), self.units[1].noise());
+        self.primary = if noise_b < noise_a { 1 } else { 0 };
+        if self.disagree {
+            self.in_use = 1 << self.primary;
+            Some([a, b][self.primary])
+        } else {
+            self.in_use = usable;
+            // Inverse-variance weighting
+            Some(wrap_angle(a + diff * noise_a / (noise_a + noise_b)))
+        }
+    }
+}
+
+impl<I2C: I2c + Peripheral<P = I2C>> SteeringSource for MpuSensor<I2C> {
+    fn angle(&mut self) -> Option<f32> {
+        self.read_angle()
+    }
+
+    fn set_zero(&mut self, zero: f32) {
+        self.turns.set_zero(zero);
+    }
+
+    fn recenter(&mut self) -> f32 {
+        self.turns.recenter()
+    }
+
+    fn still_for(&self) -> Duration {
+        self.still_since.elapsed()
+    }
+
+    fn degraded(&self) -> bool {
+        self.supervisor.degraded()
+            || !self
+                .units
+                .iter()
+                .any(|unit| unit.mpu.is_some() && !unit.health.failed())
+    }
+
+    fn health(&self) -> Health {
+        let mut health = self.units[self.primary].health;
+        let present = self.units.iter().filter(|unit| unit.present).count();
+        // Running without the redundancy that was there at boot counts as degraded
+        let lost = self
+            .units
+            .iter()
+            .any(|unit| unit.present && (unit.mpu.is_none() || unit.health.failed()));
+        health.flags.set(
+            HealthFlags::DEGRADED,
+            self.supervisor.degraded() || lost || self.disagree,
         );
+        health.flags.set(HealthFlags::DISAGREE, self.disagree);
+        if present > 1 {
+            health.sensors = self.in_use;
+        }
+        health
+    }
+
+    fn set_mount(&mut self, mount: Mount) {
+        self.mount = mount;
+        for unit in &mut self.units {
+            // The old bias estimate was in the old frame
+            unit.madgwick.reset_bias();
+            unit.gain.restart();
+        }
+    }
+
+    fn gravity(&self) -> Option<[f32; 3]> {
+        let unit = &self.units[self.primary];
+        unit.mpu.as_ref().map(|_| unit.gravity)
+    }
 
-        self.updated = Instant::now();
-
-        self.roll = quaternion_to_roll(self.q.map(|x| x), self.roll);
-        Some(self.roll)
-    }
-
-    pub fn madgwick_quaternion_update(
-        &mut self,
-        ax: f32,
-        ay: f32,
-        az: f32,
-        gyrox: f32,
-        gyroy: f32,
-        gyroz: f32,
-    ) {
-        let delta_t = self.updated.elapsed().as_secs_f32();
-
-        let &[mut q1, mut q2, mut q3, mut q4] = &self.q;
-        let [ref mut gbiasx, ref mut gbiasy, ref mut gbiasz] = &mut self.gbias;
-
-        let half_q1 = 0.5 * q1;
-        let half_q2 = 0.5 * q2;
-        let half_q3 = 0.5 * q3;
-        let half_q4 = 0.5 * q4;
-        let two_q1 = 2.0 * q1;
-        let two_q2 = 2.0 * q2;
-        let two_q3 = 2.0 * q3;
-        let two_q4 = 2.0 * q4;
-
-        let norm_acc = (ax * ax + ay * ay + az * az).sqrt();
-        if norm_acc == 0.0 {
-            return; // 防止除零错误
-        }
-        let inv_norm = 1.0 / norm_acc;
-        let ax = ax * inv_norm;
-        let ay = ay * inv_norm;
-        let az = az * inv_norm;
-
-        // 计算目标函数
-        let f1 = two_q2 * q4 - two_q1 * q3 - ax;
-        let f2 = two_q1 * q2 + two_q3 * q4 - ay;
-        let f3 = 1.0 - two_q2 * q2 - two_q3 * q3 - az;
-
-        // 计算雅可比矩阵元素
-        let j_11or24 = two_q3;
-        let j_12or23 = two_q4;
-        let j_13or22 = two_q1;
-        let j_14or21 = two_q2;
-        let j_32 = 2.0 * j_14or21;
-        let j_33 = 2.0 * j_11or24;
-
-        // 计算梯度向量 (∇f · J)
-        let mut hat_dot1 = j_14or21 * f2 - j_11or24 * f1;
-        let mut hat_dot2 = j_12or23 * f1 + j_13or22 * f2 - j_32 * f3;
-        let mut hat_dot3 = j_12or23 * f2 - j_33 * f3 - j_13or22 * f1;
-        let mut hat_dot4 = j_14or21 * f1 + j_11or24 * f2;
-
-        // 归一化梯度
-        let norm_grad =
-            (hat_dot1 * hat_dot1 + hat_dot2 * hat_dot2 + hat_dot3 * hat_dot3 + hat_dot4 * hat_dot4)
-                .sqrt();
-
-        if norm_grad > 0.0 {
-            let inv_norm_grad = 1.0 / norm_grad;
-            hat_dot1 *= inv_norm_grad;
-            hat_dot2 *= inv_norm_grad;
-            hat_dot3 *= inv_norm_grad;
-            hat_dot4 *= inv_norm_grad;
-        }
-
-        // 计算陀螺仪偏置误差
-        let gerrx = two_q1 * hat_dot2 - two_q2 * hat_dot1 - two_q3 * hat_dot4 + two_q4 * hat_dot3;
-        let gerry = two_q1 * hat_dot3 + two_q2 * hat_dot4 - two_q3 * hat_dot1 - two_q4 * hat_dot2;
-        let gerrz = two_q1 * hat_dot4 - two_q2 * hat_dot3 + two_q3 * hat_dot2 - two_q4 * hat_dot1;
-
-        // 更新陀螺仪偏置
-        *gbiasx += gerrx * delta_t * self.zeta;
-        *gbiasy += gerry * delta_t * self.zeta;
-        *gbiasz += gerrz * delta_t * self.zeta;
-
-        // 应用偏置补偿
-        let gyrox = gyrox - *gbiasx;
-        let gyroy = gyroy - *gbiasy;
-        let gyroz = gyroz - *gbiasz;
-
-        // 计算四元数导数
-        let q_dot1 = -half_q2 * gyrox - half_q3 * gyroy - half_q4 * gyroz;
-        let q_dot2 = half_q1 * gyrox + half_q3 * gyroz - half_q4 * gyroy;
-        let q_dot3 = half_q1 * gyroy - half_q2 * gyroz + half_q4 * gyrox;
-        let q_dot4 = half_q1 * gyroz + half_q2 * gyroy - half_q3 * gyrox;
-
-        // 应用梯度下降并积分
-        q1 += (q_dot1 - (self.beta * hat_dot1)) * delta_t;
-        q2 += (q_dot2 - (self.beta * hat_dot2)) * delta_t;
-        q3 += (q_dot3 - (self.beta * hat_dot3)) * delta_t;
-        q4 += (q_dot4 - (self.beta * hat_dot4)) * delta_t;
-
-        // 归一化最终四元数
-        let norm_quat = (q1 * q1 + q2 * q2 + q3 * q3 + q4 * q4).sqrt();
-        if norm_quat > 0.0 {
-            let inv_norm_quat = 1.0 / norm_quat;
-            self.q = [
-                q1 * inv_norm_quat,
-                q2 * inv_norm_quat,
-                q3 * inv_norm_quat,
-                q4 * inv_norm_quat,
-            ];
+    fn set_mode(&mut self, mode: AxisMode) {
+        self.mode = mode;
+    }
+
+    fn pitch(&self) -> Option<f32> {
+        let unit = &self.units[self.primary];
+        if !unit.usable() {
+            return None;
         }
+        Some(quaternion_to_pitch(unit.madgwick.quaternion()))
+    }
+
+    fn set_gesture_config(&mut self, config: GestureConfig) {
+        self.gestures.set_config(config);
+    }
+
+    fn take_gesture(&mut self) -> Option<Gesture> {
+        self.gesture.take()
+    }
+
+    fn set_gyro_temp_model(&mut self, sensor: usize, model: GyroTempModel) {
+        if let Some(unit) = self.units.get_mut(sensor) {
+            unit.gyro_temp = GyroTempLearner::new(model);
+        }
+    }
+
+    fn learned_gyro_temp_model(&mut self) -> Option<(usize, GyroTempModel)> {
+        let (sensor, unit) = self.units.iter_mut().enumerate().find(|(_, unit)| {
+            unit.gyro_temp_changed && unit.gyro_temp_saved.elapsed() >= GYRO_TEMP_SAVE_INTERVAL
+        })?;
+        unit.gyro_temp_changed = false;
+        unit.gyro_temp_saved = Instant::now();
+        Some((sensor, *unit.gyro_temp.model()))
     }
 }
@@ -0,0 +1,99 @@
+use esp32_ble_steering_rs::fusion::{AxisMode, GyroTempModel, Mount};
+use esp32_ble_steering_rs::gesture::{Gesture, GestureConfig};
+use esp32_ble_steering_rs::health::{Health, HealthFlags};
+use std::time::Duration;
+
+/// A sensor that measures the steering wheel angle.
+pub trait SteeringSource {
+    /// Reads the steering angle in degrees relative to the zero,
+    /// counting full turns. Returns `None` if no reading is available.
+    fn angle(&mut self) -> Option<f32>;
+
+    /// Sets the absolute angle that is reported as straight ahead.
+    ///
+    /// Should be called before the first reading, as it also resets
+    /// the turn counting to start within half a turn of `zero`.
+    fn set_zero(&mut self, zero: f32);
+
+    /// Captures the current angle as straight ahead.
+    ///
+    /// Returns the new zero, wrapped to a single turn so that it
+    /// is still valid after a reboot.
+    fn recenter(&mut self) -> f32;
+
+    /// How long the wheel has been held still.
+    fn still_for(&self) -> Duration;
+
+    /// Whether the sensor has been lost and is being recovered, or
+    /// failed its checks.
+    fn degraded(&self) -> bool {
+        false
+    }
+
+    /// Results of the sensor checks, for the diagnostics report.
+    fn health(&self) -> Health {
+        let mut health = Health::default();
+        health.flags.set(HealthFlags::DEGRADED, self.degraded());
+        health
+    }
+
+    /// Sets which rotation steers, for sources that measure more than one.
+    ///
+    /// Should be followed by [`SteeringSource::set_zero`] with the zero of
+    /// the new mode.
+    fn set_mode(&mut self, _mode: AxisMode) {}
+
+    /// Pitch in degrees, for sources that can measure it as an extra axis.
+    fn pitch(&self) -> Option<f32> {
+        None
+    }
+
+    /// Sets the gesture thresholds, for sources that recognise gestures.
+    fn set_gesture_config(&mut self, _config: GestureConfig) {}
+
+    /// Takes the last gesture recognised, if any.
+    fn take_gesture(&mut self) -> Option<Gesture> {
+        None
+    }
+
+    /// Sets how the sensor sits in the wheel hub, for sources that care.
+    fn set_mount(&mut self, _mount: Mount) {}
+
+    /// Gravity in the sensor frame, averaged over the last moments, for
+    /// sources that can calibrate their mount.
+    fn gravity(&self) -> Option<[f32; 3]> {
+        None
+    }
+
+    /// Sets a previously learned gyro bias model of one of the gyros,
+    /// by index, for sources with a gyro.
+    fn set_gyro_temp_model(&mut self, _sensor: usize, _model: GyroTempModel) {}
+
+    /// Returns a learned gyro bias model, with the index of its gyro, when
+    /// it is due to be persisted.
+    fn learned_gyro_temp_model(&mut self) -> Option<(usize, GyroTempModel)> {
+        None
+    }
+}
+
+/// The kind of steering source, selected at boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// Roll fused from the MPU gyroscope and accelerometer.
+    Imu,
+    /// AS5600 magnetic encoder on I2C.
+    As5600,
+    /// AS5048A magnetic encoder on SPI.
+    As5048,
+}
+
+impl SourceKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(SourceKind::Imu),
+            1 => Some(SourceKind::As5600),
+            2 => Some(SourceKind::As5048),
+            _ => None,
+        }
+    }
+}
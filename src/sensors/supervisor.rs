@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+/// Consecutive failed reads after which a sensor is considered lost.
+const MAX_FAILURES: u32 = 5;
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Tracks the health of a sensor and schedules re-initialisation
+/// attempts with exponential backoff while it is degraded.
+pub struct Supervisor {
+    failures: u32,
+    degraded: bool,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            failures: 0,
+            degraded: false,
+            backoff: MIN_BACKOFF,
+            retry_at: Instant::now(),
+        }
+    }
+
+    pub fn degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Records a successful read.
+    pub fn success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Records a failed read and returns whether the sensor
+    /// has just become degraded.
+    pub fn failure(&mut self) -> bool {
+        self.failures += 1;
+        if !self.degraded && self.failures >= MAX_FAILURES {
+            self.degrade();
+            return true;
+        }
+        false
+    }
+
+    /// Marks the sensor as degraded right away, e.g. when it fails to initialise.
+    pub fn degrade(&mut self) {
+        self.degraded = true;
+        self.backoff = MIN_BACKOFF;
+        self.retry_at = Instant::now() + self.backoff;
+    }
+
+    /// Whether it is time for another re-initialisation attempt.
+    pub fn should_retry(&self) -> bool {
+        self.degraded && Instant::now() >= self.retry_at
+    }
+
+    /// Records the outcome of a re-initialisation attempt.
+    pub fn retried(&mut self, ok: bool) {
+        if ok {
+            self.degraded = false;
+            self.failures = 0;
+            self.backoff = MIN_BACKOFF;
+        } else {
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            self.retry_at = Instant::now() + self.backoff;
+        }
+    }
+
+    /// The delay before the next re-initialisation attempt.
+    pub fn backoff(&self) -> Duration {
+        self.backoff
+    }
+}